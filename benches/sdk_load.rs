@@ -0,0 +1,102 @@
+//! Measures what precompiling the sdk in `build.rs` saves on every worker start
+//! and reload. Before, every load transpiled the sdk, or read it back from the
+//! module cache when enabled. Now the transpiled modules are served from the
+//! binary.
+//!
+//! Run with `cargo bench --bench sdk_load`.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    env, fs,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+    time::Instant,
+};
+
+use deno_ast::{EmitOptions, MediaType, ParseParams, SourceTextInfo};
+
+static PRECOMPILED: &[(&str, &str)] = include!(concat!(env!("OUT_DIR"), "/sdk_modules.rs"));
+
+const ITERATIONS: u32 = 20;
+
+// the byte count returned by `load` keeps the work from being optimized away
+fn measure(name: &str, mut load: impl FnMut() -> usize) {
+    let bytes = load();
+    let started_at = Instant::now();
+    for _ in 0..ITERATIONS {
+        assert_eq!(load(), bytes);
+    }
+    let per_load = started_at.elapsed() / ITERATIONS;
+    println!(
+        "{:<28} {:>10.3} ms per load ({} bytes)",
+        name,
+        per_load.as_secs_f64() * 1000.0,
+        bytes
+    );
+}
+
+fn main() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("sdk");
+    let sources: Vec<(&str, String)> = PRECOMPILED
+        .iter()
+        .map(|(path, _)| (*path, fs::read_to_string(root.join(path)).unwrap()))
+        .collect();
+
+    measure("transpiled on load", || {
+        sources
+            .iter()
+            .map(|(path, source)| {
+                let parsed = deno_ast::parse_module(ParseParams {
+                    specifier: format!("dcs:///{}", path),
+                    source: SourceTextInfo::from_string(source.clone()),
+                    media_type: MediaType::TypeScript,
+                    capture_tokens: false,
+                    scope_analysis: false,
+                    maybe_syntax: None,
+                })
+                .unwrap();
+                parsed
+                    .transpile(&EmitOptions {
+                        inline_source_map: true,
+                        ..Default::default()
+                    })
+                    .unwrap()
+                    .text
+                    .len()
+            })
+            .sum()
+    });
+
+    // the loader hashes the source to find its cache entry, then reads it back
+    let cache_dir = env::temp_dir().join(format!("dcs-ts-bench-{}", std::process::id()));
+    fs::create_dir_all(&cache_dir).unwrap();
+    for (path, code) in PRECOMPILED {
+        fs::write(cache_dir.join(path.replace('/', "_")), code).unwrap();
+    }
+    measure("read from the module cache", || {
+        sources
+            .iter()
+            .map(|(path, source)| {
+                let mut hasher = DefaultHasher::new();
+                source.hash(&mut hasher);
+                hasher.finish();
+                fs::read_to_string(cache_dir.join(path.replace('/', "_")))
+                    .unwrap()
+                    .len()
+            })
+            .sum()
+    });
+    fs::remove_dir_all(&cache_dir).unwrap();
+
+    measure("precompiled", || {
+        sources
+            .iter()
+            .filter_map(|(path, _)| {
+                PRECOMPILED
+                    .iter()
+                    .find(|(module, _)| module == path)
+                    .map(|(_, code)| code.len())
+            })
+            .sum()
+    });
+}
@@ -0,0 +1,77 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use deno_ast::{EmitOptions, MediaType, ParseParams, SourceTextInfo};
+
+// tooling that runs outside of the runtime, not part of the embedded sdk
+const EXCLUDED_DIRS: &[&str] = &["command"];
+
+fn collect_modules(root: &Path, dir: &Path, modules: &mut Vec<String>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let relative = path
+            .strip_prefix(root)
+            .unwrap()
+            .to_string_lossy()
+            .replace('\\', "/");
+        if path.is_dir() {
+            if !EXCLUDED_DIRS.contains(&relative.as_str()) {
+                collect_modules(root, &path, modules);
+            }
+        } else if relative.ends_with(".ts") {
+            modules.push(relative);
+        }
+    }
+}
+
+/// Transpiles the sdk ahead of time, so workers can load it without running the
+/// TypeScript compiler on every startup and reload.
+///
+/// This is not a V8 startup snapshot. deno_core at the pinned revision can only
+/// snapshot classic scripts, not ES modules like the sdk, and the runtime bootstrap
+/// already starts from the snapshot deno_runtime builds. `benches/sdk_load.rs`
+/// measures what this saves per load.
+fn main() {
+    let root = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("sdk");
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    println!("cargo:rerun-if-changed=sdk");
+
+    let mut modules = vec![];
+    collect_modules(&root, &root, &mut modules);
+    modules.sort();
+
+    let mut table = String::from("&[\n");
+    for module in modules {
+        let source = fs::read_to_string(root.join(&module)).unwrap();
+        let parsed = deno_ast::parse_module(ParseParams {
+            specifier: format!("dcs:///{}", module),
+            source: SourceTextInfo::from_string(source),
+            media_type: MediaType::TypeScript,
+            capture_tokens: false,
+            scope_analysis: false,
+            maybe_syntax: None,
+        })
+        .unwrap_or_else(|e| panic!("failed to parse sdk/{}: {}", module, e));
+        let code = parsed
+            .transpile(&EmitOptions {
+                inline_source_map: true,
+                ..Default::default()
+            })
+            .unwrap_or_else(|e| panic!("failed to transpile sdk/{}: {}", module, e))
+            .text;
+
+        let out_path = out_dir.join("sdk").join(module.replace(".ts", ".js"));
+        fs::create_dir_all(out_path.parent().unwrap()).unwrap();
+        fs::write(&out_path, code).unwrap();
+        table.push_str(&format!(
+            "    ({:?}, include_str!({:?})),\n",
+            module,
+            out_path.to_string_lossy()
+        ));
+    }
+    table.push(']');
+
+    fs::write(out_dir.join("sdk_modules.rs"), table).unwrap();
+}
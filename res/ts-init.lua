@@ -29,11 +29,30 @@ end
 local ts = require("dcs_ts")
 _G.ts = ts
 
+local function getMissionInfo()
+  local info = {
+    theatre = env.mission.theatre
+  }
+
+  local ok, name = pcall(env.getValueDictByKey, env.mission.sortie)
+  if ok then
+    info.name = name
+  end
+
+  -- the mission file is only known to the gui environment
+  local fileOk, file = pcall(net.dostring_in, "gui", "return DCS.getMissionFilename()")
+  if fileOk and file ~= nil and file ~= "" then
+    info.file = file
+  end
+
+  return info
+end
+
 local isMissionEnv = DCS == nil
 if isMissionEnv then
   env.info("[dcs-ts] mission env is initializing typescript runtime")
   local ok, result = pcall(function()
-    ts.initialize(lfs.writedir())
+    ts.initialize(lfs.writedir(), getMissionInfo())
   end)
   if not ok then
     env.info("[dcs-ts] initialization failed: " .. tostring(result))
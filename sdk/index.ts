@@ -4,12 +4,14 @@ import * as Command from "./command.ts";
 import * as Common from "./common.ts";
 import * as Controller from "./controller.ts";
 import * as Group from "./group.ts";
+import * as Kv from "./kv.ts";
 import * as MissionEvent from "./event.ts";
 import * as Land from "./land.ts";
 import * as Mission from "./mission.ts";
 import * as Net from "./net.ts";
 import * as Runtime from "./runtime.ts";
 import * as Spot from "./spot.ts";
+import * as Sqlite from "./sqlite.ts";
 import * as Timer from "./timer.ts";
 import * as Trigger from "./trigger.ts";
 import * as Unit from "./unit.ts";
@@ -22,12 +24,14 @@ import * as World from "./world.ts";
 (window as any).Common = Common;
 (window as any).Controller = Controller;
 (window as any).Group = Group;
+(window as any).Kv = Kv;
 (window as any).MissionEvent = MissionEvent;
 (window as any).Land = Land;
 (window as any).Mission = Mission;
 (window as any).Net = Net;
 (window as any).Runtime = Runtime;
 (window as any).Spot = Spot;
+(window as any).Sqlite = Sqlite;
 (window as any).Timer = Timer;
 (window as any).Trigger = Trigger;
 (window as any).Unit = Unit;
@@ -0,0 +1,75 @@
+const DenoCore = (Deno as any).core;
+
+export type KvEntry<T = unknown> = {
+  key: string;
+  value: T;
+};
+
+export type KvMutation =
+  | { type: "set"; key: string; value: unknown }
+  | { type: "delete"; key: string };
+
+/**
+ * A precondition of an atomic write. Without a `value` the key must not exist,
+ * a `value` of null requires a stored null.
+ */
+export type KvCheck = {
+  key: string;
+  value?: unknown;
+};
+
+/**
+ * Returns the value stored under `key` in the persistent key-value store, or
+ * null if there is none. A stored null is returned as null as well, a check
+ * without a value tells the two apart: `await atomic([], [{ key }])` is true
+ * only if nothing is stored under `key`.
+ */
+export async function get<T = unknown>(key: string): Promise<T | null> {
+  return await DenoCore.opAsync("op_dcs_kv_get", key);
+}
+
+/**
+ * Stores a JSON serializable value under `key`. The returned promise resolves
+ * once the value has been written to disk.
+ */
+export async function set(key: string, value: unknown): Promise<void> {
+  return await DenoCore.opAsync("op_dcs_kv_set", { key, value });
+}
+
+/**
+ * Removes the value stored under `key`.
+ */
+export async function remove(key: string): Promise<void> {
+  return await DenoCore.opAsync("op_dcs_kv_delete", key);
+}
+
+/**
+ * Returns all entries whose key starts with `prefix`, ordered by key.
+ *
+ * @param prefix - the key prefix, e.g. `"credits/"`.
+ * @param limit - the maximum number of entries to return.
+ */
+export async function list<T = unknown>(
+  prefix: string,
+  limit?: number,
+): Promise<Array<KvEntry<T>>> {
+  return await DenoCore.opAsync("op_dcs_kv_list", { prefix, limit });
+}
+
+/**
+ * Applies all mutations at once, or none of them if any of the checks fails.
+ *
+ * @param mutations - the writes to apply.
+ * @param checks - conditions on the current values which must all hold.
+ * @returns whether the mutations were applied.
+ */
+export async function atomic(
+  mutations: Array<KvMutation>,
+  checks: Array<KvCheck> = [],
+): Promise<boolean> {
+  // an undefined value would arrive as null
+  checks = checks.map(({ key, value }) =>
+    value === undefined ? { key } : { key, value }
+  );
+  return await DenoCore.opAsync("op_dcs_kv_atomic", { checks, mutations });
+}
@@ -113,7 +113,8 @@ export async function waitChannel<T = unknown>(
 }
 
 /**
- * Sends a message on a channel opened with ChannelDirection.TO_LUA.
+ * Sends a message on a channel opened with ChannelDirection.TO_LUA. Lua takes
+ * the messages sent so far with `ts.channel_receive(channel.id)`.
  *
  * @param channel - the destination channel
  * @param value - the message value
@@ -128,13 +129,81 @@ export async function sendChannel(
   });
 }
 
+export type ScriptOptions<T = unknown> = {
+  args: Array<string>;
+  env: Record<string, string>;
+  config: T;
+};
+
+/**
+ * Returns the deployment specific options (`args`, `env` and `config`) configured
+ * for a script within `ts.json`.
+ *
+ * @param url - the url of the script, generally `import.meta.url`. Can be omitted
+ *   if the script runs within its own worker.
+ */
+export function getScriptOptions<T = unknown>(url?: string): ScriptOptions<T> {
+  return DenoCore.opSync("op_dcs_script_options", url);
+}
+
+export type ScriptReport = {
+  path: string;
+  worker: string;
+  loaded: boolean;
+  error: {
+    message: string;
+    stack: string | null;
+  } | null;
+};
+
+export type LoadReport = {
+  scripts: Array<ScriptReport>;
+  /**
+   * Tasks of reloaded workers that were dropped before running, or whose results
+   * arrived after the reload.
+   */
+  discardedTasks: number;
+};
+
+/**
+ * Returns the load outcome of every configured script, including the error for
+ * scripts that failed to load.
+ */
+export function getLoadReport(): LoadReport {
+  return DenoCore.opSync("op_dcs_load_report");
+}
+
+/**
+ * Stores a JSON serializable value under `key` to hand it over to the next
+ * generation of this worker, usually from a `reload` event listener. That event
+ * is dispatched before the next generation starts, while `unload` only runs
+ * after it took over with blue/green reloads. Throws if the value exceeds the
+ * configured stash size limits.
+ *
+ * @param key - the name of the entry, replacing any previous entry.
+ * @param value - the value to store.
+ */
+export function stash(key: string, value: unknown) {
+  DenoCore.opSync("op_dcs_stash_put", { key, value });
+}
+
+/**
+ * Returns and removes the value stored under `key` by a previous generation of
+ * this worker, or null if there is none.
+ *
+ * @param key - the name of the entry.
+ */
+export function takeStash<T = unknown>(key: string): T | null {
+  return DenoCore.opSync("op_dcs_stash_take", key);
+}
+
 /**
  * Reload the TypeScript runtime.
  */
 export function reload() {
-  DenoCore.opSync("op_dcs_reload", (window as any).reloaderId);
+  DenoCore.opSync("op_dcs_reload", (globalThis as any).reloaderId);
 }
 
-(window as any).reload = reload;
-(window as any).runTask = runTask;
-(window as any).luaEval = luaEval;
+(globalThis as any).reload = reload;
+(globalThis as any).runTask = runTask;
+(globalThis as any).luaEval = luaEval;
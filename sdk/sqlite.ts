@@ -0,0 +1,104 @@
+const DenoCore = (Deno as any).core;
+
+/**
+ * Statement parameters, an array for positional (`?`) parameters or an object
+ * for named (`:name`) parameters. Booleans are stored as 0/1, arrays and
+ * objects as JSON text.
+ */
+export type SqlParams = Array<unknown> | Record<string, unknown>;
+
+export type Row = Record<string, unknown>;
+
+export type ExecuteResult = {
+  changes: number;
+  lastInsertRowid: number;
+};
+
+export type Statement = {
+  sql: string;
+  params?: SqlParams;
+};
+
+/**
+ * A statement run many times with different parameters. It only holds the SQL:
+ * the database compiles every statement once and keeps the most recently used
+ * ones compiled, so running the same SQL again skips parsing it.
+ */
+export class PreparedStatement {
+  constructor(private database: Database, private sql: string) {}
+
+  run(params?: SqlParams): Promise<ExecuteResult> {
+    return this.database.execute(this.sql, params);
+  }
+
+  all<T = Row>(params?: SqlParams): Promise<Array<T>> {
+    return this.database.query<T>(this.sql, params);
+  }
+}
+
+/**
+ * A SQLite database stored in the `Data` directory of the write dir. Statements
+ * run on a background thread and never block the event loop.
+ */
+export class Database {
+  private constructor(private rid: number) {}
+
+  /**
+   * Opens or creates a database. The file is opened on a background thread as
+   * well.
+   *
+   * @param path - path of the database file relative to `Data`, or `:memory:`.
+   */
+  static async open(path: string): Promise<Database> {
+    return new Database(await DenoCore.opAsync("op_dcs_sqlite_open", path));
+  }
+
+  /**
+   * Runs a statement which doesn't return rows, like an insert or update.
+   */
+  async execute(sql: string, params?: SqlParams): Promise<ExecuteResult> {
+    return await DenoCore.opAsync("op_dcs_sqlite_execute", {
+      rid: this.rid,
+      sql,
+      params,
+    });
+  }
+
+  /**
+   * Runs a query and returns its rows as objects keyed by column name.
+   */
+  async query<T = Row>(sql: string, params?: SqlParams): Promise<Array<T>> {
+    return await DenoCore.opAsync("op_dcs_sqlite_query", {
+      rid: this.rid,
+      sql,
+      params,
+    });
+  }
+
+  /**
+   * Prepares a statement to be run repeatedly, see `PreparedStatement`.
+   */
+  prepare(sql: string): PreparedStatement {
+    return new PreparedStatement(this, sql);
+  }
+
+  /**
+   * Runs all statements within a single transaction, either all of them are
+   * applied or, if any fails, none of them.
+   */
+  async transaction(
+    statements: Array<Statement>,
+  ): Promise<Array<ExecuteResult>> {
+    return await DenoCore.opAsync("op_dcs_sqlite_transaction", {
+      rid: this.rid,
+      statements,
+    });
+  }
+
+  /**
+   * Closes the database.
+   */
+  close() {
+    DenoCore.close(this.rid);
+  }
+}
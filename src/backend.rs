@@ -0,0 +1,241 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{mpsc, Arc, Mutex},
+};
+
+use deno_core::{anyhow::Error, error::generic_error};
+
+use crate::{
+    runtime::{Runtime, Task, TaskResult},
+    RUNTIME,
+};
+
+/// What a backend answering tasks itself hands back to the scripts.
+#[derive(Debug)]
+pub enum Reply {
+    Result(TaskResult),
+    Message {
+        channel: u64,
+        message: serde_json::Value,
+    },
+}
+
+/// The side of the bridge running the tasks queued by scripts, DCS Lua when
+/// running as a Lua module. Results of tasks and messages for channels towards
+/// the scripts are handed back through [`complete`] and [`publish`].
+///
+/// The runtime calls the backend it was started with while holding its lock, so
+/// it must not block or call back into the runtime. Backends answering tasks
+/// themselves, like the ones of the [`runner`](crate::runner), are instead
+/// submitted the tasks polled from the runtime and return their answers through
+/// [`take_replies`](Self::take_replies).
+pub trait DcsBackend {
+    /// Hands over a task queued by a script.
+    fn submit(&mut self, task: Task);
+
+    /// Takes the submitted tasks, for hosts pulling them like the Lua bridge does.
+    /// Backends pushing tasks to their host have none.
+    fn poll(&mut self) -> Vec<Task> {
+        vec![]
+    }
+
+    /// Drops submitted tasks of a retired worker generation that no host took
+    /// yet, returning their ids.
+    fn discard(&mut self, _generation: u64) -> Vec<u64> {
+        vec![]
+    }
+
+    /// Takes the results and channel messages for the scripts, in the order they
+    /// are handed over.
+    fn take_replies(&mut self) -> Vec<Reply> {
+        vec![]
+    }
+
+    /// Whether the backend has nothing left to feed the scripts, ending a run of
+    /// the runner.
+    fn finished(&self) -> bool {
+        false
+    }
+}
+
+/// Keeps submitted tasks until a host polls them. Used by the Lua module, whose
+/// bridge polls on every simulation frame, and by the standalone runner.
+#[derive(Default)]
+pub struct QueueBackend {
+    tasks: VecDeque<Task>,
+}
+
+impl DcsBackend for QueueBackend {
+    fn submit(&mut self, task: Task) {
+        self.tasks.push_back(task);
+    }
+
+    fn poll(&mut self) -> Vec<Task> {
+        self.tasks.drain(..).collect()
+    }
+
+    fn discard(&mut self, generation: u64) -> Vec<u64> {
+        let (discarded, kept): (Vec<Task>, Vec<Task>) = self
+            .tasks
+            .drain(..)
+            .partition(|task| task.generation == generation);
+        self.tasks = kept.into();
+        discarded.into_iter().map(|task| task.id).collect()
+    }
+}
+
+// tasks sent through the channel which the host didn't receive yet
+#[derive(Default)]
+struct InFlight {
+    // task id -> generation
+    sent: HashMap<u64, u64>,
+    // discarded tasks the receiver skips
+    discarded: HashSet<u64>,
+}
+
+/// Sends submitted tasks to the [`TaskReceiver`] returned by
+/// [`ChannelBackend::new`], for hosts running tasks on their own thread or
+/// forwarding them over IPC.
+pub struct ChannelBackend {
+    tx: mpsc::Sender<Task>,
+    in_flight: Arc<Mutex<InFlight>>,
+}
+
+impl ChannelBackend {
+    pub fn new() -> (Self, TaskReceiver) {
+        let (tx, rx) = mpsc::channel();
+        let in_flight = Arc::new(Mutex::new(InFlight::default()));
+        let receiver = TaskReceiver {
+            rx,
+            in_flight: in_flight.clone(),
+        };
+        (Self { tx, in_flight }, receiver)
+    }
+}
+
+impl DcsBackend for ChannelBackend {
+    fn submit(&mut self, task: Task) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let (id, generation) = (task.id, task.generation);
+        // the host went away, the task stays unanswered like within a stopped
+        //  mission
+        if self.tx.send(task).is_err() {
+            log::warn!("dropped task, the host of the channel backend is gone");
+            return;
+        }
+        in_flight.sent.insert(id, generation);
+    }
+
+    fn discard(&mut self, generation: u64) -> Vec<u64> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let ids: Vec<u64> = in_flight
+            .sent
+            .iter()
+            .filter(|(_, task_generation)| **task_generation == generation)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &ids {
+            in_flight.sent.remove(id);
+            in_flight.discarded.insert(*id);
+        }
+        ids
+    }
+}
+
+/// Receives the tasks of a [`ChannelBackend`], skipping the ones discarded
+/// before the host got to them. Iterating blocks until the backend is dropped.
+pub struct TaskReceiver {
+    rx: mpsc::Receiver<Task>,
+    in_flight: Arc<Mutex<InFlight>>,
+}
+
+impl TaskReceiver {
+    /// Blocks until the next task arrives.
+    pub fn recv(&self) -> Result<Task, mpsc::RecvError> {
+        loop {
+            if let Some(task) = self.accept(self.rx.recv()?) {
+                return Ok(task);
+            }
+        }
+    }
+
+    /// Returns the next task if there is one.
+    pub fn try_recv(&self) -> Result<Task, mpsc::TryRecvError> {
+        loop {
+            if let Some(task) = self.accept(self.rx.try_recv()?) {
+                return Ok(task);
+            }
+        }
+    }
+
+    fn accept(&self, task: Task) -> Option<Task> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        in_flight.sent.remove(&task.id);
+        if in_flight.discarded.remove(&task.id) {
+            None
+        } else {
+            Some(task)
+        }
+    }
+}
+
+impl Iterator for TaskReceiver {
+    type Item = Task;
+
+    fn next(&mut self) -> Option<Task> {
+        self.recv().ok()
+    }
+}
+
+fn with_runtime<T>(f: impl FnOnce(&mut Runtime) -> T) -> Result<T, Error> {
+    let mut runtime = RUNTIME.lock().unwrap();
+    runtime
+        .as_mut()
+        .map(f)
+        .ok_or_else(|| generic_error("invalid runtime"))
+}
+
+/// Takes the tasks waiting for a host pulling them.
+pub fn poll() -> Result<Vec<Task>, Error> {
+    with_runtime(|runtime| runtime.poll_tasks())
+}
+
+/// Hands the result of a task back to the script which queued it.
+pub fn complete(result: TaskResult) -> Result<(), Error> {
+    with_runtime(|runtime| runtime.complete_task(result))
+}
+
+/// Sends a message on a channel towards the scripts, returning whether it was
+/// delivered. Messages to closed or full channels are dropped.
+pub fn publish(channel: u64, message: serde_json::Value) -> Result<bool, Error> {
+    with_runtime(|runtime| runtime.send_user_channel_message(channel, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(id: u64, generation: u64) -> Task {
+        Task {
+            id,
+            worker: "main".to_string(),
+            generation,
+            target: "getTime".to_string(),
+            args: None,
+        }
+    }
+
+    #[test]
+    fn channel_backend_discards_tasks_not_received_yet() {
+        let (mut backend, tasks) = ChannelBackend::new();
+        backend.submit(task(1, 1));
+        assert_eq!(tasks.try_recv().unwrap().id, 1);
+        backend.submit(task(2, 1));
+        backend.submit(task(3, 2));
+
+        // the task the host already received is left alone
+        assert_eq!(backend.discard(1), vec![2]);
+        assert_eq!(tasks.try_recv().unwrap().id, 3);
+        assert!(tasks.try_recv().is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! Runs the scripts configured in `Config/ts.json` of a write dir outside of DCS,
+//! with their tasks answered by fixtures, a Lua stand-in or a recorded journal
+//! instead of the bridge.
+//!
+//! The default build is the Lua module loaded by DCS, which has no Lua of its
+//! own, so the runner is built with the embedded one:
+//!
+//! ```text
+//! cargo build --release --no-default-features --features harness --bin dcs-ts-run
+//! ```
+
+use std::{env, path::Path, process, time::Duration};
+
+use dcs_ts::{
+    backend::QueueBackend,
+    runner::{self, FixtureBackend, JournalBackend, LuaBackend},
+    MissionInfo,
+};
+
+const USAGE: &str = "usage: dcs-ts-run <write dir> [--fixtures <file> | --lua <file> | --journal <file>] [--idle-timeout <seconds>]";
+
+// scripts which stopped queueing tasks for this long are assumed to be done
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Args {
+    write_dir: String,
+    backend: Option<(String, String)>,
+    idle_timeout: Option<Duration>,
+}
+
+fn parse_args() -> Option<Args> {
+    let mut args = env::args().skip(1);
+    let mut write_dir = None;
+    let mut backend = None;
+    let mut idle_timeout = Some(DEFAULT_IDLE_TIMEOUT);
+    while let Some(arg) = args.next() {
+        if arg == "--idle-timeout" {
+            let secs: u64 = args.next()?.parse().ok()?;
+            // zero runs until the scripts exit the process
+            idle_timeout = Some(Duration::from_secs(secs)).filter(|it| !it.is_zero());
+        } else if ["--fixtures", "--lua", "--journal"].contains(&arg.as_str()) {
+            if backend.is_some() {
+                return None;
+            }
+            backend = Some((arg, args.next()?));
+        } else if write_dir.is_none() && !arg.starts_with("--") {
+            write_dir = Some(arg);
+        } else {
+            return None;
+        }
+    }
+    Some(Args {
+        write_dir: write_dir?,
+        backend,
+        idle_timeout,
+    })
+}
+
+// tasks are kept for `runner::run` to poll
+fn boot(write_dir: &str, mission: Option<MissionInfo>) -> Result<(), String> {
+    runner::boot(write_dir, mission, Box::new(QueueBackend::default())).map_err(|e| e.to_string())
+}
+
+fn run(args: Args) -> Result<i32, String> {
+    let (kind, path) = args
+        .backend
+        .unwrap_or_else(|| ("--fixtures".to_string(), String::new()));
+    let path = Path::new(&path);
+
+    let report = match kind.as_str() {
+        "--journal" => {
+            let mut journal = JournalBackend::open(path).map_err(|e| e.to_string())?;
+            boot(&args.write_dir, journal.mission())?;
+            runner::run(&mut journal, args.idle_timeout).map_err(|e| e.to_string())?;
+
+            let report = journal.report();
+            println!("replayed {} of {} records", report.replayed, report.records);
+            if let Some(divergence) = report.divergence {
+                println!("replay diverged: {}", divergence);
+                return Ok(1);
+            }
+            return Ok(0);
+        }
+        "--lua" => {
+            let mut backend = LuaBackend::open(path).map_err(|e| e.to_string())?;
+            boot(&args.write_dir, None)?;
+            runner::run(&mut backend, args.idle_timeout)
+        }
+        _ => {
+            // without fixtures every task fails
+            let mut backend = if path.as_os_str().is_empty() {
+                FixtureBackend::default()
+            } else {
+                FixtureBackend::open(path).map_err(|e| e.to_string())?
+            };
+            boot(&args.write_dir, None)?;
+            runner::run(&mut backend, args.idle_timeout)
+        }
+    }
+    .map_err(|e| e.to_string())?;
+
+    println!(
+        "answered {} tasks and sent {} messages",
+        report.tasks, report.messages
+    );
+    Ok(0)
+}
+
+fn main() {
+    let args = match parse_args() {
+        Some(args) => args,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    match run(args) {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    }
+}
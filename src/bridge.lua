@@ -1,5 +1,24 @@
 local fns = {}
 
+-- lua side resources created by tasks (event handlers, menus, marks, spots,
+--  ...) are owned by the runtime generation of the worker which created them, so
+--  they can be released when that worker is reloaded
+local ownedResources = {}
+local currentGeneration = nil
+
+local function ownResource(release)
+  if currentGeneration == nil then
+    return
+  end
+
+  local resources = ownedResources[currentGeneration]
+  if resources == nil then
+    resources = {}
+    ownedResources[currentGeneration] = resources
+  end
+  table.insert(resources, release)
+end
+
 function exportPosition(pos)
   if pos == nil then
     return nil
@@ -170,6 +189,11 @@ fns.triggerActionMarkToAll = function(args)
   else
     error("invalid mark target")
   end
+
+  local id = args.id
+  ownResource(function()
+    trigger.action.removeMark(id)
+  end)
 end
 
 fns.triggerActionRemoveMark = function(args)
@@ -181,6 +205,11 @@ fns.triggerActionLine = function(args)
   local endpos = coord.LLtoLO(args.end_[1], args.end_[2], args.end_[3])
   trigger.action.lineToAll(args.coalition, args.id, startpos, endpos, args.color, args.lineType, args.readOnly,
     args.message)
+
+  local id = args.id
+  ownResource(function()
+    trigger.action.removeMark(id)
+  end)
 end
 
 fns.getTime = function(args)
@@ -522,6 +551,25 @@ end
 
 commandId = 1
 
+local function ownMenuItem(target, path)
+  if target == nil then
+    ownResource(function()
+      missionCommands.removeItem(path)
+    end)
+  elseif target.group ~= nil then
+    -- the group may be gone by the time the item is released
+    local groupId = ensureGroup(target.group):getID()
+    ownResource(function()
+      missionCommands.removeItemForGroup(groupId, path)
+    end)
+  elseif target.coalition ~= nil then
+    local coalition = target.coalition
+    ownResource(function()
+      missionCommands.removeItemForCoalition(coalition, path)
+    end)
+  end
+end
+
 fns.missionCommandsAddCommand = function(args)
   local id = commandId
   commandId = commandId + 1
@@ -574,6 +622,7 @@ fns.missionCommandsAddCommand = function(args)
   else
     error("invalid command target")
   end
+  ownMenuItem(args.target, path)
 
   return {
     id = id,
@@ -583,8 +632,9 @@ fns.missionCommandsAddCommand = function(args)
 end
 
 fns.missionCommandsAddSubMenu = function(args)
+  local result = nil
   if args.target == nil then
-    return {
+    result = {
       path = missionCommands.addSubMenu(args.name, args.path)
     }
   elseif args.target.group ~= nil then
@@ -592,18 +642,20 @@ fns.missionCommandsAddSubMenu = function(args)
     if group == nil then
       error("no group found by name " .. args.target.group)
     end
-    return {
+    result = {
       path = missionCommands.addSubMenuForGroup(group:getID(), args.name, args.path),
       target = args.target
     }
   elseif args.target.coalition ~= nil then
-    return {
+    result = {
       path = missionCommands.addCommandForCoalition(args.target.coalition, args.name, args.path),
       target = args.target
     }
   else
     error("invalid command target")
   end
+  ownMenuItem(args.target, result.path)
+  return result
 end
 
 fns.missionCommandsRemove = function(args)
@@ -650,7 +702,15 @@ fns.unitWatcherCreate = function(args)
   -- todo: implement lerp
   local timeBetween = args.updateIntervalSeconds / 1
 
+  ownResource(function()
+    unitWatchers[id] = nil
+  end)
+
   timer.scheduleFunction(function()
+    if unitWatchers[id] == nil then
+      return nil
+    end
+
     local updated = {}
     local removed = {}
     for unitName, value in pairs(unitWatchers[id].units) do
@@ -836,16 +896,23 @@ fns.getMarkPanels = function(args)
   return World.getMarkPanels()
 end
 
+local function ownSpot(spot)
+  ownResource(function()
+    Spot.destroy(spot)
+  end)
+  return spot
+end
+
 fns.spotCreateLaser = function(args)
   local source = getObject(args.source)
   local target = coord.LLtoLO(args.target[1], args.target[2], args.target[3])
-  return exportSpot(Spot.createLaser(source, args.offset, target, args.code))
+  return exportSpot(ownSpot(Spot.createLaser(source, args.offset, target, args.code)))
 end
 
 fns.spotCreateInfraRed = function(args)
   local source = getObject(args.source)
   local target = coord.LLtoLO(args.target[1], args.target[2], args.target[3])
-  return exportSpot(Spot.spotCreateInfraRed(source, args.offset, target))
+  return exportSpot(ownSpot(Spot.createInfraRed(source, args.offset, target)))
 end
 
 fns.spotSetPoint = function(args)
@@ -895,6 +962,13 @@ end
 fns.triggerActionEffectSmokeBig = function(args)
   local pos = coord.LLtoLO(args.position[1], args.position[2], args.position[3])
   trigger.action.effectSmokeBig(pos, args.type, args.density, args.name)
+
+  if args.name ~= nil then
+    local name = args.name
+    ownResource(function()
+      trigger.action.effectSmokeStop(name)
+    end)
+  end
 end
 
 fns.triggerActionEffectSmokeStop = function(args)
@@ -903,6 +977,7 @@ end
 
 fns.triggerActionSmoke = function(args)
   local pos = coord.LLtoLO(args.position[1], args.position[2], args.position[3])
+  -- marker smoke can't be stopped and is left to expire on its own
   trigger.action.smoke(pos, args.color)
 end
 
@@ -994,17 +1069,42 @@ fns.createEventProducer = function(args)
     end
   end
   world.addEventHandler(eventHandler)
+  ownResource(function()
+    world.removeEventHandler(eventHandler)
+  end)
+end
+
+-- functions only called by the runtime itself, scripts can't queue these
+local internalFns = {}
+
+internalFns._releaseGeneration = function(args)
+  local resources = ownedResources[args.generation]
+  ownedResources[args.generation] = nil
+  if resources == nil then
+    return 0
+  end
+
+  for _, release in ipairs(resources) do
+    local ok, err = pcall(release)
+    if not ok then
+      ts.log("[dcs-ts] failed to release resource of generation " .. tostring(args.generation) .. ": " ..
+               tostring(err))
+    end
+  end
+  return #resources
 end
 
 local function processQueuedTasks()
-  local queuedTasks = ts.get_queued_tasks()
+  local queuedTasks = ts.get_queued_tasks(timer.getTime())
   if queuedTasks == nil then
     return
   end
 
   local taskResults = {}
   for index, value in ipairs(queuedTasks) do
-    local ok, result = pcall(fns[value.target], value.args)
+    currentGeneration = value.generation
+    local ok, result = pcall(internalFns[value.target] or fns[value.target], value.args)
+    currentGeneration = nil
     local taskResult = {
       id = value.id,
       result = {
@@ -1024,14 +1124,44 @@ local function processQueuedTasks()
   end
 end
 
+local logFunctions = {
+  info = env.info,
+  warning = env.warning,
+  error = env.error
+}
+
+local function processLogRecords()
+  local records = ts.get_log_records()
+  if records == nil then
+    return
+  end
+
+  for _, record in ipairs(records) do
+    local logFunction = logFunctions[record.level] or env.info
+    logFunction("[dcs-ts] " .. record.message)
+  end
+end
+
 env.info("[dcs-ts] starting task bridge")
 local pollingRate = 500
 local stepTime = 1 / pollingRate
+-- log records are mirrored into dcs.log in batches, at most every logPollInterval
+local logPollInterval = 0.25
+local nextLogPoll = timer.getTime()
 timer.scheduleFunction(function()
   local ok, result = pcall(processQueuedTasks)
   if not ok then
     ts.log("[dcs-ts] failed to run processQueuedTasks: " .. tostring(result))
   end
-  return timer.getTime() + stepTime
+
+  local now = timer.getTime()
+  if now >= nextLogPoll then
+    nextLogPoll = now + logPollInterval
+    local logOk, logResult = pcall(processLogRecords)
+    if not logOk then
+      ts.log("[dcs-ts] failed to run processLogRecords: " .. tostring(logResult))
+    end
+  end
+  return now + stepTime
 end, nil, timer.getTime() + stepTime)
 env.info("[dcs-ts] ready and running")
@@ -0,0 +1,818 @@
+use std::{collections::HashMap, fmt, fs, io, path::Path};
+
+use mlua::LuaSerdeExt;
+use schemars::JsonSchema;
+use serde::{
+    de::{self, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+use thiserror::Error;
+
+use crate::{
+    inspector::InspectorConfig, journal::JournalConfig, logging::LoggingConfig,
+    permissions::PermissionsConfig, remote::ModulesConfig, stash::StashConfig,
+};
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Optional reference to the JSON schema of this file, for editor support.
+    #[serde(rename = "$schema", default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+    pub write_dir: Option<String>,
+    pub sdk_path: Option<String>,
+    pub import_map: Option<String>,
+    pub development: bool,
+    pub debugging: bool,
+    pub scripts: Vec<ScriptConfig>,
+    pub break_on_first_statement: Option<bool>,
+    #[serde(default)]
+    pub inspector: InspectorConfig,
+    #[serde(default)]
+    pub permissions: PermissionsConfig,
+    #[serde(default)]
+    pub isolate_scripts: bool,
+    #[serde(default)]
+    pub modules: ModulesConfig,
+    #[serde(default)]
+    pub missions: Vec<MissionRule>,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub stash: StashConfig,
+    #[serde(default)]
+    pub reload: ReloadConfig,
+    #[serde(default)]
+    pub journal: JournalConfig,
+    #[serde(skip)]
+    pub mission: Option<MissionInfo>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReloadMode {
+    /// Stops the worker, then starts it again.
+    InPlace,
+    /// Starts the new worker next to the old one and only switches over once it
+    /// loaded without errors, otherwise the old worker keeps running.
+    BlueGreen,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReloadConfig {
+    pub mode: ReloadMode,
+    /// How long a blue/green reload waits for the new worker to load.
+    pub ready_timeout_secs: u64,
+}
+
+impl Default for ReloadConfig {
+    fn default() -> Self {
+        Self {
+            mode: ReloadMode::InPlace,
+            ready_timeout_secs: 30,
+        }
+    }
+}
+
+/// Identity of the mission the runtime was started for, reported by the bridge.
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+pub struct MissionInfo {
+    pub file: Option<String>,
+    pub name: Option<String>,
+    pub theatre: Option<String>,
+}
+
+/// Additional scripts which are only loaded for matching missions.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MissionRule {
+    #[serde(rename = "match")]
+    pub matcher: MissionMatch,
+    pub scripts: Vec<ScriptConfig>,
+}
+
+/// Conditions a mission has to meet, every given condition must match. `file` and
+/// `name` are globs (`*` and `?`) matched against the mission file name (or path
+/// when the pattern contains a path separator) and mission name. All comparisons
+/// ignore case.
+#[derive(Debug, Clone, Deserialize, Serialize, Default, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct MissionMatch {
+    pub file: Option<String>,
+    pub name: Option<String>,
+    pub theatre: Option<String>,
+}
+
+impl MissionMatch {
+    pub fn matches(&self, mission: &MissionInfo) -> bool {
+        let file_matches = match (&self.file, &mission.file) {
+            (None, _) => true,
+            (Some(pattern), Some(file)) => {
+                let file = file.replace('\\', "/");
+                let target = if pattern.contains('/') || pattern.contains('\\') {
+                    file.as_str()
+                } else {
+                    file.rsplit('/').next().unwrap_or(&file)
+                };
+                glob_match(
+                    &pattern.replace('\\', "/").to_lowercase(),
+                    &target.to_lowercase(),
+                )
+            }
+            (Some(_), None) => false,
+        };
+        let name_matches = match (&self.name, &mission.name) {
+            (None, _) => true,
+            (Some(pattern), Some(name)) => {
+                glob_match(&pattern.to_lowercase(), &name.to_lowercase())
+            }
+            (Some(_), None) => false,
+        };
+        let theatre_matches = match (&self.theatre, &mission.theatre) {
+            (None, _) => true,
+            (Some(expected), Some(theatre)) => expected.eq_ignore_ascii_case(theatre),
+            (Some(_), None) => false,
+        };
+        file_matches && name_matches && theatre_matches
+    }
+}
+
+/// Matches `text` against a glob `pattern` supporting `*` and `?`.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// A script loaded on initialization, either just a path or an object with
+/// additional options.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ScriptConfig {
+    Path(String),
+    Options(ScriptOptions),
+}
+
+// implemented by hand instead of `#[serde(untagged)]` so errors within script
+//  options (like unknown fields) are reported instead of a generic mismatch
+impl<'de> Deserialize<'de> for ScriptConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ScriptConfigVisitor;
+
+        impl<'de> Visitor<'de> for ScriptConfigVisitor {
+            type Value = ScriptConfig;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a script path or an object with script options")
+            }
+
+            fn visit_str<E: de::Error>(self, path: &str) -> Result<Self::Value, E> {
+                Ok(ScriptConfig::Path(path.to_string()))
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, map: M) -> Result<Self::Value, M::Error> {
+                ScriptOptions::deserialize(de::value::MapAccessDeserializer::new(map))
+                    .map(ScriptConfig::Options)
+            }
+        }
+
+        deserializer.deserialize_any(ScriptConfigVisitor)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptOptions {
+    pub path: String,
+    pub permissions: Option<PermissionsConfig>,
+    pub group: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub config: serde_json::Value,
+}
+
+/// The deployment specific settings of a script, as returned to it by
+/// `op_dcs_script_options`.
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct ScriptSettings {
+    pub args: Vec<String>,
+    pub env: HashMap<String, String>,
+    pub config: serde_json::Value,
+}
+
+/// The set of scripts that run together within a single worker, along with the
+/// settings they share.
+#[derive(Debug, Clone)]
+pub struct WorkerSpec {
+    pub name: String,
+    pub scripts: Vec<ScriptConfig>,
+    pub permissions: PermissionsConfig,
+    /// The `Deno.args` of the worker.
+    pub args: Vec<String>,
+    /// Variables the worker sees through `Deno.env` on top of the environment of
+    /// the process.
+    pub env: HashMap<String, String>,
+}
+
+impl WorkerSpec {
+    /// Finds the settings of the script loaded from `specifier`. When no specifier
+    /// is given the worker must only contain a single script.
+    pub fn script_settings(&self, specifier: Option<&str>) -> Option<ScriptSettings> {
+        let script = match specifier {
+            Some(specifier) => self.scripts.iter().find(|script| {
+                deno_core::resolve_path(script.path())
+                    .map(|module| module.as_str() == specifier)
+                    .unwrap_or(false)
+            }),
+            None if self.scripts.len() == 1 => self.scripts.first(),
+            None => None,
+        };
+        script.map(|script| script.settings())
+    }
+
+    fn new(name: String, permissions: PermissionsConfig, settings: ScriptSettings) -> Self {
+        Self {
+            name,
+            scripts: vec![],
+            permissions,
+            args: settings.args,
+            env: settings.env,
+        }
+    }
+
+    // scripts can only share a worker when they agree on everything granted or
+    //  handed to the worker as a whole
+    fn accepts(&self, permissions: &PermissionsConfig, settings: &ScriptSettings) -> bool {
+        self.permissions == *permissions && self.args == settings.args && self.env == settings.env
+    }
+}
+
+pub const MAIN_WORKER_NAME: &str = "main";
+
+impl ScriptConfig {
+    pub fn path(&self) -> &str {
+        match self {
+            ScriptConfig::Path(path) => path,
+            ScriptConfig::Options(options) => &options.path,
+        }
+    }
+
+    pub fn permissions(&self) -> Option<&PermissionsConfig> {
+        match self {
+            ScriptConfig::Path(_) => None,
+            ScriptConfig::Options(options) => options.permissions.as_ref(),
+        }
+    }
+
+    /// The permissions the script runs with, the global ones plus its own.
+    pub fn effective_permissions(&self, global: &PermissionsConfig) -> PermissionsConfig {
+        match self.permissions() {
+            Some(permissions) => global.merge(permissions),
+            None => global.clone(),
+        }
+    }
+
+    pub fn group(&self) -> Option<&str> {
+        match self {
+            ScriptConfig::Path(_) => None,
+            ScriptConfig::Options(options) => options.group.as_deref(),
+        }
+    }
+
+    pub fn settings(&self) -> ScriptSettings {
+        match self {
+            ScriptConfig::Path(_) => ScriptSettings::default(),
+            ScriptConfig::Options(options) => ScriptSettings {
+                args: options.args.clone(),
+                env: options.env.clone(),
+                config: options.config.clone(),
+            },
+        }
+    }
+}
+
+impl Config {
+    /// The scripts to load for the current mission, the base `scripts` followed by
+    /// the scripts of every matching rule in `missions`.
+    pub fn active_scripts(&self) -> Vec<ScriptConfig> {
+        let mission = self.mission.clone().unwrap_or_default();
+        let mut scripts = self.scripts.clone();
+        for rule in &self.missions {
+            if rule.matcher.matches(&mission) {
+                scripts.extend(rule.scripts.iter().cloned());
+            }
+        }
+        scripts
+    }
+
+    /// Splits the configured scripts into the workers they will run in. By default
+    /// every script shares a single worker, with `isolate_scripts` each script gets
+    /// its own worker unless it names a `group` shared with other scripts.
+    ///
+    /// Permissions, `Deno.args` and `Deno.env` are per worker, so a script whose
+    /// permissions, args or env differ from the worker it would share always gets
+    /// a worker of its own.
+    pub fn worker_specs(&self) -> Vec<WorkerSpec> {
+        let mut workers: Vec<WorkerSpec> = vec![];
+        if !self.isolate_scripts {
+            // the main worker runs with the global permissions and no args or env
+            workers.push(WorkerSpec::new(
+                MAIN_WORKER_NAME.to_string(),
+                self.permissions.clone(),
+                ScriptSettings::default(),
+            ));
+        }
+
+        for script in self.active_scripts() {
+            let permissions = script.effective_permissions(&self.permissions);
+            let settings = script.settings();
+            let name = match script.group() {
+                _ if !self.isolate_scripts => MAIN_WORKER_NAME,
+                Some(group) => group,
+                None => script.path(),
+            };
+            let name = match workers.iter_mut().find(|worker| worker.name == name) {
+                Some(worker) if worker.accepts(&permissions, &settings) => {
+                    worker.scripts.push(script);
+                    continue;
+                }
+                Some(_) => {
+                    log::warn!(
+                        "script {} runs in its own worker since its permissions, args or env \
+                         differ from the other scripts of worker {}",
+                        script.path(),
+                        name
+                    );
+                    script.path().to_string()
+                }
+                None => name.to_string(),
+            };
+            let mut worker = WorkerSpec::new(name, permissions, settings);
+            worker.scripts.push(script);
+            workers.push(worker);
+        }
+        workers
+    }
+}
+
+/// An error loading `Config/ts.json`, pointing at the offending location.
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("failed to read config {path}: {source}")]
+    Read {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("invalid config {path} at line {line} column {column}: {message}")]
+    Invalid {
+        path: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl Config {
+    /// Reads and validates the config at `path`. The file is JSON which may
+    /// contain comments and trailing commas, unknown fields are rejected.
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.display().to_string(),
+            source,
+        })?;
+        Config::parse(&contents).map_err(|(line, column, message)| ConfigError::Invalid {
+            path: path.display().to_string(),
+            line,
+            column,
+            message,
+        })
+    }
+
+    fn parse(contents: &str) -> Result<Config, (usize, usize, String)> {
+        let json = strip_jsonc(contents);
+        let mut deserializer = serde_json::Deserializer::from_str(&json);
+        let config: Config =
+            serde_path_to_error::deserialize(&mut deserializer).map_err(|error| {
+                let path = error.path().to_string();
+                let inner = error.into_inner();
+                let message = describe_error(&inner);
+                let message = if path.is_empty() || path == "." {
+                    message
+                } else {
+                    format!("{}: {}", path, message)
+                };
+                (inner.line(), inner.column(), message)
+            })?;
+        deserializer
+            .end()
+            .map_err(|error| (error.line(), error.column(), describe_error(&error)))?;
+        Ok(config)
+    }
+
+    /// Writes the JSON schema of the config file to `path`, so editors can
+    /// validate and complete `ts.json` by setting `"$schema"`.
+    pub fn write_schema(path: &Path) -> io::Result<()> {
+        let schema = schemars::schema_for!(Config);
+        let contents = serde_json::to_vec_pretty(&schema)?;
+        if fs::read(path).map(|it| it == contents).unwrap_or(false) {
+            return Ok(());
+        }
+        fs::write(path, contents)
+    }
+}
+
+// serde reports unknown fields as "unknown field `x`, expected one of `a`, `b`",
+//  the expected list is replaced with the closest match when there is one
+fn describe_error(error: &serde_json::Error) -> String {
+    let message = error.to_string();
+    // serde_json appends the location itself, it is reported separately
+    let message = match message.rfind(" at line ") {
+        Some(index) => message[..index].to_string(),
+        None => message,
+    };
+    let rest = match message.strip_prefix("unknown field `") {
+        Some(rest) => rest,
+        None => return message,
+    };
+    let (field, expected) = match rest.split_once('`') {
+        Some(parts) => parts,
+        None => return message,
+    };
+    let candidates: Vec<&str> = expected.split('`').skip(1).step_by(2).collect();
+    let suggestion = candidates
+        .iter()
+        .map(|candidate| (strsim::jaro_winkler(field, candidate), candidate))
+        .filter(|(score, _)| *score >= 0.8)
+        .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    match suggestion {
+        Some((_, candidate)) => format!("unknown field `{}`, did you mean `{}`?", field, candidate),
+        None => message,
+    }
+}
+
+/// Blanks out `//` and `/* */` comments and trailing commas so the contents can
+/// be parsed as plain JSON. Everything is replaced with whitespace in place,
+/// keeping line and column numbers of errors intact.
+fn strip_jsonc(contents: &str) -> String {
+    // works on bytes, serde_json reports columns in bytes and blanking a
+    //  multi-byte character with a single space would shift them
+    let mut out = contents.as_bytes().to_vec();
+    let mut i = 0;
+    let mut in_string = false;
+    // index of the last comma outside a string not yet followed by a value
+    let mut pending_comma: Option<usize> = None;
+
+    while i < out.len() {
+        let c = out[i];
+        if in_string {
+            match c {
+                b'\\' => i += 1,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            i += 1;
+            continue;
+        }
+
+        match (c, out.get(i + 1)) {
+            (b'/', Some(b'/')) => {
+                while i < out.len() && out[i] != b'\n' {
+                    out[i] = b' ';
+                    i += 1;
+                }
+                continue;
+            }
+            (b'/', Some(b'*')) => {
+                let mut closed = false;
+                while i < out.len() {
+                    if out[i] == b'*' && out.get(i + 1) == Some(&b'/') {
+                        out[i] = b' ';
+                        out[i + 1] = b' ';
+                        i += 2;
+                        closed = true;
+                        break;
+                    }
+                    if out[i] != b'\n' {
+                        out[i] = b' ';
+                    }
+                    i += 1;
+                }
+                if !closed {
+                    break;
+                }
+                continue;
+            }
+            (b',', _) => pending_comma = Some(i),
+            (b'}', _) | (b']', _) => {
+                if let Some(comma) = pending_comma.take() {
+                    out[comma] = b' ';
+                }
+            }
+            (c, _) if c.is_ascii_whitespace() => {}
+            (b'"', _) => {
+                in_string = true;
+                pending_comma = None;
+            }
+            _ => pending_comma = None,
+        }
+        i += 1;
+    }
+
+    // only whole comments and ascii commas are blanked, so it stays valid utf-8
+    String::from_utf8(out).expect("blanked jsonc is valid utf-8")
+}
+
+impl<'lua> mlua::FromLua<'lua> for Config {
+    fn from_lua(lua_value: mlua::Value<'lua>, lua: &'lua mlua::Lua) -> mlua::Result<Self> {
+        let config: Config = lua.from_value(lua_value)?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mission(file: &str) -> MissionInfo {
+        MissionInfo {
+            file: Some(file.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn file_match(pattern: &str) -> MissionMatch {
+        MissionMatch {
+            file: Some(pattern.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn glob_star_matches_any_run_of_characters() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("*training*.miz", "pvp_training_02.miz"));
+        assert!(glob_match("*training*.miz", "training.miz"));
+        assert!(glob_match("a**b", "ab"));
+        assert!(glob_match("*ab", "aab"));
+        assert!(!glob_match("*training*.miz", "training.mizz"));
+        assert!(!glob_match("a*b", "ba"));
+    }
+
+    #[test]
+    fn glob_question_mark_matches_exactly_one_character() {
+        assert!(glob_match("op?.miz", "op1.miz"));
+        assert!(glob_match("*a?c", "xxabc"));
+        assert!(!glob_match("op?.miz", "op.miz"));
+        assert!(!glob_match("op?.miz", "op12.miz"));
+    }
+
+    #[test]
+    fn mission_match_ignores_case() {
+        assert!(file_match("*Training*.MIZ").matches(&mission("C:\\Missions\\my_training.miz")));
+
+        let name = MissionMatch {
+            name: Some("Op *".to_string()),
+            theatre: Some("caucasus".to_string()),
+            ..Default::default()
+        };
+        let info = MissionInfo {
+            name: Some("OP Red Dawn".to_string()),
+            theatre: Some("Caucasus".to_string()),
+            ..Default::default()
+        };
+        assert!(name.matches(&info));
+    }
+
+    #[test]
+    fn mission_match_uses_the_file_name_unless_the_pattern_has_a_separator() {
+        let file = "C:\\Users\\dcs\\Saved Games\\Missions\\pvp\\op1.miz";
+        assert!(file_match("op?.miz").matches(&mission(file)));
+        assert!(!file_match("pvp*").matches(&mission(file)));
+        assert!(file_match("*\\pvp\\*.miz").matches(&mission(file)));
+        assert!(file_match("*/pvp/*.miz").matches(&mission(file)));
+        assert!(file_match("*/pvp/*.miz").matches(&mission("/home/dcs/pvp/op1.miz")));
+        assert!(!file_match("*\\pve\\*.miz").matches(&mission(file)));
+    }
+
+    #[test]
+    fn mission_match_requires_every_given_condition() {
+        assert!(MissionMatch::default().matches(&MissionInfo::default()));
+        assert!(!file_match("*.miz").matches(&MissionInfo::default()));
+
+        let rule = MissionMatch {
+            file: Some("*.miz".to_string()),
+            theatre: Some("Syria".to_string()),
+            ..Default::default()
+        };
+        assert!(!rule.matches(&MissionInfo {
+            theatre: Some("Caucasus".to_string()),
+            ..mission("op1.miz")
+        }));
+    }
+
+    #[test]
+    fn worker_specs_group_scripts_by_what_they_share() {
+        let cases: &[(&str, &str, &[(&str, &[&str])])] = &[
+            (
+                "scripts share the main worker by default",
+                r#""scripts": ["a.ts", { "path": "b.ts", "config": { "port": 1 } }]"#,
+                &[("main", &["a.ts", "b.ts"])],
+            ),
+            (
+                "the main worker exists without scripts",
+                r#""scripts": []"#,
+                &[("main", &[])],
+            ),
+            (
+                "permissions beyond the global ones isolate a script",
+                r#""permissions": { "net": ["a.com"] },
+                   "scripts": ["a.ts", { "path": "b.ts", "permissions": { "net": ["b.com"] } }]"#,
+                &[("main", &["a.ts"]), ("b.ts", &["b.ts"])],
+            ),
+            (
+                "permissions within the global ones do not",
+                r#""permissions": { "net": ["a.com"] },
+                   "scripts": [
+                     "a.ts",
+                     { "path": "b.ts", "permissions": { "net": ["a.com"], "read": false } }
+                   ]"#,
+                &[("main", &["a.ts", "b.ts"])],
+            ),
+            (
+                "taking a global grant away isolates a script",
+                r#""permissions": { "net": ["a.com"] },
+                   "scripts": ["a.ts", { "path": "b.ts", "permissions": { "net": false } }]"#,
+                &[("main", &["a.ts"]), ("b.ts", &["b.ts"])],
+            ),
+            (
+                "args isolate a script, even when they match another one",
+                r#""scripts": [
+                     "a.ts",
+                     { "path": "b.ts", "args": ["x"] },
+                     { "path": "c.ts", "args": ["x"] }
+                   ]"#,
+                &[
+                    ("main", &["a.ts"]),
+                    ("b.ts", &["b.ts"]),
+                    ("c.ts", &["c.ts"]),
+                ],
+            ),
+            (
+                "env isolates a script",
+                r#""scripts": ["a.ts", { "path": "b.ts", "env": { "X": "1" } }]"#,
+                &[("main", &["a.ts"]), ("b.ts", &["b.ts"])],
+            ),
+            (
+                "isolated scripts get a worker each",
+                r#""isolate_scripts": true, "scripts": ["a.ts", "b.ts"]"#,
+                &[("a.ts", &["a.ts"]), ("b.ts", &["b.ts"])],
+            ),
+            (
+                "isolated scripts share the worker of their group",
+                r#""isolate_scripts": true,
+                   "scripts": [
+                     { "path": "a.ts", "group": "g" },
+                     "b.ts",
+                     { "path": "c.ts", "group": "g", "config": { "port": 1 } }
+                   ]"#,
+                &[("g", &["a.ts", "c.ts"]), ("b.ts", &["b.ts"])],
+            ),
+            (
+                "a group member with its own args leaves the group",
+                r#""isolate_scripts": true,
+                   "scripts": [
+                     { "path": "a.ts", "group": "g", "args": ["x"] },
+                     { "path": "b.ts", "group": "g", "args": ["x"] },
+                     { "path": "c.ts", "group": "g", "args": ["y"] }
+                   ]"#,
+                &[("g", &["a.ts", "b.ts"]), ("c.ts", &["c.ts"])],
+            ),
+        ];
+
+        for (description, fields, expected) in cases {
+            let config = Config::parse(&format!(
+                r#"{{ "development": false, "debugging": false, {} }}"#,
+                fields
+            ))
+            .unwrap();
+            let workers: Vec<(&str, Vec<&str>)> = config
+                .worker_specs()
+                .iter()
+                .map(|worker| {
+                    (
+                        worker.name.as_str(),
+                        worker.scripts.iter().map(|script| script.path()).collect(),
+                    )
+                })
+                .collect();
+            let expected: Vec<(&str, Vec<&str>)> = expected
+                .iter()
+                .map(|(name, scripts)| (*name, scripts.to_vec()))
+                .collect();
+            assert_eq!(workers, expected, "{}", description);
+        }
+    }
+
+    #[test]
+    fn jsonc_comments_are_ignored() {
+        let config = Config::parse(
+            r#"{
+                // line comment
+                "development": true, /* block
+                comment */ "debugging": false,
+                "scripts": [] // trailing
+            }"#,
+        )
+        .unwrap();
+        assert!(config.development);
+        assert!(config.scripts.is_empty());
+    }
+
+    #[test]
+    fn jsonc_keeps_comment_markers_within_strings() {
+        let config = Config::parse(
+            r#"{
+                "development": false,
+                "debugging": false,
+                "sdk_path": "https://example.com/sdk /* not a comment */",
+                "scripts": [{ "path": "//server/share/main.ts" }]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.sdk_path.as_deref(),
+            Some("https://example.com/sdk /* not a comment */")
+        );
+        assert_eq!(config.scripts[0].path(), "//server/share/main.ts");
+    }
+
+    #[test]
+    fn jsonc_allows_trailing_commas() {
+        let config = Config::parse(
+            r#"{
+                "development": false,
+                "debugging": false,
+                "scripts": [{ "path": "a.ts", }, { "path": "b.ts" },],
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.scripts.len(), 2);
+
+        // only trailing commas are dropped
+        assert!(
+            Config::parse(r#"{ "development": false,, "debugging": false, "scripts": [] }"#)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn jsonc_errors_point_at_the_original_position() {
+        let (line, column, message) = Config::parse(
+            "{\n  /* größe */ \"development\": 1,\n  \"debugging\": false, \"scripts\": []\n}",
+        )
+        .unwrap_err();
+        assert_eq!(line, 2);
+        // the column serde_json reports is in bytes, past the `1`
+        assert_eq!(column, "  /* größe */ \"development\": 1".len());
+        assert!(message.starts_with("development: "), "{}", message);
+    }
+
+    #[test]
+    fn schema_is_only_written_when_changed() {
+        let path = std::env::temp_dir().join(format!("dcs-ts-schema-{}.json", std::process::id()));
+        Config::write_schema(&path).unwrap();
+        let written = fs::metadata(&path).unwrap().modified().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        Config::write_schema(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), written);
+
+        fs::write(&path, "{}").unwrap();
+        Config::write_schema(&path).unwrap();
+        assert_ne!(fs::read(&path).unwrap(), b"{}");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+};
+
+use deno_core::{
+    error::{type_error, AnyError},
+    OpState,
+};
+use deno_runtime::permissions::Permissions;
+
+/// The environment of a worker as seen through `Deno.env`: the environment of the
+/// process with the `env` of its scripts on top. Changes made by scripts stay
+/// within the worker.
+pub struct WorkerEnv {
+    // `None` marks variables deleted within the worker
+    vars: HashMap<String, Option<String>>,
+    // handed to the worker by the config, so using them needs no `env` permission
+    configured: HashSet<String>,
+}
+
+impl WorkerEnv {
+    pub fn new(vars: HashMap<String, String>) -> Self {
+        Self {
+            configured: vars.keys().cloned().collect(),
+            vars: vars
+                .into_iter()
+                .map(|(key, value)| (key, Some(value)))
+                .collect(),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        match self.vars.get(key) {
+            Some(value) => value.clone(),
+            None => env::var(key).ok(),
+        }
+    }
+
+    fn to_map(&self) -> HashMap<String, String> {
+        let mut vars: HashMap<String, String> = env::vars().collect();
+        for (key, value) in &self.vars {
+            match value {
+                Some(value) => vars.insert(key.clone(), value.clone()),
+                None => vars.remove(key),
+            };
+        }
+        vars
+    }
+}
+
+fn check_permission(
+    worker_env: &WorkerEnv,
+    permissions: &mut Permissions,
+    key: &str,
+) -> Result<(), AnyError> {
+    if worker_env.configured.contains(key) {
+        return Ok(());
+    }
+    permissions.env.check(key)
+}
+
+// `WorkerEnv` and `Permissions` both live in the op state, which only lends out
+//  one of them mutably at a time
+fn check_access(state: &mut OpState, key: &str) -> Result<(), AnyError> {
+    let worker_env = state.take::<WorkerEnv>();
+    let result = check_permission(&worker_env, state.borrow_mut::<Permissions>(), key);
+    state.put(worker_env);
+    result
+}
+
+// same validation as the deno ops these replace
+fn check_key(key: &str) -> Result<(), AnyError> {
+    if key.is_empty() || key.contains(&['=', '\0'] as &[char]) {
+        return Err(type_error("Key contains invalid characters."));
+    }
+    Ok(())
+}
+
+// listing the environment also lists the variables of the process, so it always
+//  needs the `env` permission
+pub fn op_env(state: &mut OpState, _: (), _: ()) -> Result<HashMap<String, String>, AnyError> {
+    state.borrow_mut::<Permissions>().env.check_all()?;
+    Ok(state.borrow::<WorkerEnv>().to_map())
+}
+
+pub fn op_get_env(state: &mut OpState, key: String, _: ()) -> Result<Option<String>, AnyError> {
+    check_access(state, &key)?;
+    check_key(&key)?;
+    Ok(state.borrow::<WorkerEnv>().get(&key))
+}
+
+pub fn op_set_env(state: &mut OpState, key: String, value: String) -> Result<(), AnyError> {
+    check_access(state, &key)?;
+    check_key(&key)?;
+    if value.contains('\0') {
+        return Err(type_error("Value contains invalid characters."));
+    }
+    state
+        .borrow_mut::<WorkerEnv>()
+        .vars
+        .insert(key, Some(value));
+    Ok(())
+}
+
+pub fn op_delete_env(state: &mut OpState, key: String, _: ()) -> Result<(), AnyError> {
+    check_access(state, &key)?;
+    check_key(&key)?;
+    state.borrow_mut::<WorkerEnv>().vars.insert(key, None);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permissions::PermissionsConfig;
+
+    #[test]
+    fn configured_variables_need_no_env_permission() {
+        let worker_env = WorkerEnv::new(HashMap::from([(
+            "HTTP_LOG_LEVEL".to_string(),
+            "debug".to_string(),
+        )]));
+        let mut permissions = PermissionsConfig::default().to_permissions();
+        assert!(check_permission(&worker_env, &mut permissions, "HTTP_LOG_LEVEL").is_ok());
+        assert_eq!(worker_env.get("HTTP_LOG_LEVEL").as_deref(), Some("debug"));
+        assert!(check_permission(&worker_env, &mut permissions, "PATH").is_err());
+    }
+}
@@ -0,0 +1,484 @@
+-- stand-ins for the parts of the DCS mission scripting environment used by the
+--  bridge. Everything the bridge does to the mission is recorded into
+--  `harness.calls`, and the mission (time, units, groups, zones, events) is
+--  scripted through the `harness` table.
+harness = {
+  now = 0,
+  time0 = 43200,
+  calls = {},
+  logs = {},
+  groups = {},
+  units = {},
+  zones = {},
+  terrainHeight = 0,
+  eventHandlers = {},
+  scheduled = {},
+  nextScheduleId = 1
+}
+
+function harness.record(name, ...)
+  table.insert(harness.calls, {
+    name = name,
+    time = harness.now,
+    args = {...}
+  })
+end
+
+-- a table whose functions not given in `functions` only record their calls
+local function recorder(prefix, functions)
+  return setmetatable(functions or {}, {
+    __index = function(_, name)
+      return function(...)
+        harness.record(prefix .. "." .. name, ...)
+      end
+    end
+  })
+end
+
+local function log(level)
+  return function(message)
+    table.insert(harness.logs, {
+      level = level,
+      message = message
+    })
+    if harness.verbose then
+      print("[" .. level .. "] " .. tostring(message))
+    end
+  end
+end
+
+env = {
+  info = log("info"),
+  warning = log("warning"),
+  error = log("error"),
+  mission = {
+    theatre = "Caucasus",
+    sortie = "DictKey_sortie"
+  },
+  getValueDictByKey = function(key)
+    return key
+  end
+}
+
+timer = {
+  getTime = function()
+    return harness.now
+  end,
+  getAbsTime = function()
+    return harness.time0 + harness.now
+  end,
+  getTime0 = function()
+    return harness.time0
+  end,
+  scheduleFunction = function(fn, arg, time)
+    local id = harness.nextScheduleId
+    harness.nextScheduleId = id + 1
+    harness.scheduled[id] = {
+      fn = fn,
+      arg = arg,
+      time = time
+    }
+    return id
+  end,
+  removeFunction = function(id)
+    harness.scheduled[id] = nil
+  end,
+  setFunctionTime = function(id, time)
+    if harness.scheduled[id] ~= nil then
+      harness.scheduled[id].time = time
+    end
+  end
+}
+
+-- runs every scheduled function due within the next `seconds` of mission time in
+--  order, like DCS a function returning a number is rescheduled to that time
+function harness.advance(seconds)
+  local target = harness.now + seconds
+  while true do
+    local nextId, nextEntry = nil, nil
+    for id, entry in pairs(harness.scheduled) do
+      if entry.time <= target and (nextEntry == nil or entry.time < nextEntry.time or
+        (entry.time == nextEntry.time and id < nextId)) then
+        nextId, nextEntry = id, entry
+      end
+    end
+    if nextEntry == nil then
+      break
+    end
+
+    harness.scheduled[nextId] = nil
+    harness.now = math.max(harness.now, nextEntry.time)
+    local ok, result = pcall(nextEntry.fn, nextEntry.arg, harness.now)
+    if not ok then
+      env.error("scheduled function failed: " .. tostring(result))
+    elseif type(result) == "number" then
+      harness.scheduled[nextId] = {
+        fn = nextEntry.fn,
+        arg = nextEntry.arg,
+        time = result
+      }
+    end
+  end
+  harness.now = target
+end
+
+coord = {
+  -- a flat projection of roughly 111km per degree is enough for round trips
+  LLtoLO = function(lat, lon, alt)
+    return {
+      x = lat * 111000,
+      y = alt or 0,
+      z = lon * 111000
+    }
+  end,
+  LOtoLL = function(pos)
+    return pos.x / 111000, pos.z / 111000, pos.y
+  end
+}
+
+land = recorder("land", {
+  SurfaceType = {
+    LAND = 1,
+    SHALLOW_WATER = 2,
+    WATER = 3,
+    ROAD = 4,
+    RUNWAY = 5
+  },
+  getHeight = function(point)
+    return harness.terrainHeight
+  end,
+  getSurfaceHeightWithSeabed = function(point)
+    return harness.terrainHeight, 0
+  end,
+  getSurfaceType = function(point)
+    return land.SurfaceType.LAND
+  end,
+  isVisible = function(from, to)
+    return true
+  end
+})
+
+trigger = {
+  smokeColor = {
+    Green = 0,
+    Red = 1,
+    White = 2,
+    Orange = 3,
+    Blue = 4
+  },
+  flareColor = {
+    Green = 0,
+    Red = 1,
+    White = 2,
+    Yellow = 3
+  },
+  action = recorder("trigger.action", {
+    getZone = function(name)
+      return harness.zones[name]
+    end
+  })
+}
+
+Object = {
+  Category = {
+    UNIT = 1,
+    WEAPON = 2,
+    STATIC = 3,
+    BASE = 4,
+    SCENERY = 5,
+    CARGO = 6
+  },
+  getCategory = function(object)
+    return object:getCategory()
+  end,
+  getName = function(object)
+    return object:getName()
+  end,
+  getTypeName = function(object)
+    return object:getTypeName()
+  end,
+  destroy = function(object)
+    object:destroy()
+  end
+}
+
+Group = {
+  Category = {
+    AIRPLANE = 0,
+    HELICOPTER = 1,
+    GROUND = 2,
+    SHIP = 3,
+    TRAIN = 4
+  }
+}
+Group.__index = Group
+
+function Group.getByName(name)
+  return harness.groups[name]
+end
+
+function Group:getID()
+  return self.id
+end
+
+function Group:getName()
+  return self.name
+end
+
+function Group:getCoalition()
+  return self.coalition
+end
+
+function Group:getCategory()
+  return self.category
+end
+
+function Group:getUnits()
+  return self.units
+end
+
+function Group:getUnit(index)
+  return self.units[index]
+end
+
+function Group:getSize()
+  return #self.units
+end
+
+function Group:isExist()
+  return harness.groups[self.name] == self
+end
+
+function Group:destroy()
+  harness.record("Group.destroy", self.name)
+  for _, unit in ipairs(self.units) do
+    harness.units[unit.name] = nil
+  end
+  harness.groups[self.name] = nil
+end
+
+Unit = {}
+Unit.__index = Unit
+
+function Unit.getByName(name)
+  return harness.units[name]
+end
+
+function Unit:getID()
+  return self.id
+end
+
+function Unit:getName()
+  return self.name
+end
+
+function Unit:getCallsign()
+  return self.callsign
+end
+
+function Unit:getCoalition()
+  return self.group.coalition
+end
+
+function Unit:getTypeName()
+  return self.type
+end
+
+function Unit:getCategory()
+  return Object.Category.UNIT
+end
+
+function Unit:getDesc()
+  return {
+    typeName = self.type
+  }
+end
+
+function Unit:getPoint()
+  return self.position
+end
+
+function Unit:getVelocity()
+  return self.velocity
+end
+
+function Unit:getNumber()
+  return self.number
+end
+
+function Unit:getGroup()
+  return self.group
+end
+
+function Unit:getPlayerName()
+  return self.playerName
+end
+
+function Unit:getLife()
+  return self.life
+end
+
+function Unit:getLife0()
+  return self.life0
+end
+
+function Unit:getFuel()
+  return self.fuel
+end
+
+function Unit:isActive()
+  return true
+end
+
+function Unit:isExist()
+  return harness.units[self.name] == self
+end
+
+function Unit:destroy()
+  harness.record("Unit.destroy", self.name)
+  harness.units[self.name] = nil
+end
+
+local nextObjectId = 1
+
+-- adds a group to the mission, `data` is `{name, coalition, category, units}`
+--  with each unit being `{name, type, position, velocity, playerName}`
+function harness.addGroup(data)
+  local group = setmetatable({
+    id = nextObjectId,
+    name = data.name,
+    coalition = data.coalition or coalition.side.BLUE,
+    category = data.category or Group.Category.AIRPLANE,
+    units = {}
+  }, Group)
+  nextObjectId = nextObjectId + 1
+
+  for index, unitData in ipairs(data.units or {}) do
+    local unit = setmetatable({
+      id = nextObjectId,
+      name = unitData.name,
+      callsign = unitData.callsign or unitData.name,
+      type = unitData.type or "F-16C_50",
+      position = unitData.position or {
+        x = 0,
+        y = 0,
+        z = 0
+      },
+      velocity = unitData.velocity or {
+        x = 0,
+        y = 0,
+        z = 0
+      },
+      playerName = unitData.playerName,
+      number = index,
+      life = unitData.life or 1,
+      life0 = unitData.life or 1,
+      fuel = unitData.fuel or 1,
+      group = group
+    }, Unit)
+    nextObjectId = nextObjectId + 1
+    table.insert(group.units, unit)
+    harness.units[unit.name] = unit
+  end
+
+  harness.groups[group.name] = group
+  return group
+end
+
+coalition = {
+  side = {
+    NEUTRAL = 0,
+    RED = 1,
+    BLUE = 2
+  },
+  getGroups = function(side, category)
+    local result = {}
+    for _, group in pairs(harness.groups) do
+      if group.coalition == side and (category == nil or group.category == category) then
+        table.insert(result, group)
+      end
+    end
+    return result
+  end,
+  getPlayers = function(side)
+    local result = {}
+    for _, unit in pairs(harness.units) do
+      if unit.playerName ~= nil and unit.group.coalition == side then
+        table.insert(result, unit)
+      end
+    end
+    return result
+  end,
+  getAirbases = function(side)
+    return {}
+  end,
+  addGroup = function(country, category, data)
+    harness.record("coalition.addGroup", country, category, data)
+    return harness.addGroup({
+      name = data.name,
+      category = category,
+      units = data.units
+    })
+  end
+}
+
+world = {
+  event = {
+    S_EVENT_SHOT = 1,
+    S_EVENT_HIT = 2,
+    S_EVENT_DEAD = 8,
+    S_EVENT_BIRTH = 15,
+    S_EVENT_PLAYER_ENTER_UNIT = 20,
+    S_EVENT_PLAYER_LEAVE_UNIT = 21,
+    S_EVENT_MARK_ADDED = 25
+  },
+  addEventHandler = function(handler)
+    table.insert(harness.eventHandlers, handler)
+  end,
+  removeEventHandler = function(handler)
+    for index, existing in ipairs(harness.eventHandlers) do
+      if existing == handler then
+        table.remove(harness.eventHandlers, index)
+        return
+      end
+    end
+  end,
+  getAirbases = function(side)
+    return {}
+  end,
+  searchObjects = function()
+    return {}
+  end
+}
+
+-- delivers `event` to every event handler, like DCS `time` defaults to now
+function harness.dispatchEvent(event)
+  if event.time == nil then
+    event.time = harness.now
+  end
+  -- handlers may remove themselves while the event is delivered
+  local handlers = {}
+  for index, handler in ipairs(harness.eventHandlers) do
+    handlers[index] = handler
+  end
+  for _, handler in ipairs(handlers) do
+    handler:onEvent(event)
+  end
+end
+
+missionCommands = recorder("missionCommands", {
+  addCommand = function(name, path, fn, arg)
+    harness.record("missionCommands.addCommand", name, path)
+    return {name}
+  end,
+  addSubMenu = function(name, path)
+    harness.record("missionCommands.addSubMenu", name, path)
+    return {name}
+  end
+})
+
+net = recorder("net", {
+  get_player_list = function()
+    return {}
+  end
+})
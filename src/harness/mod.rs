@@ -0,0 +1,135 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use mlua::prelude::*;
+
+#[cfg(test)]
+mod tests;
+
+// mission time advanced per step by `run_until`, the rate the bridge polls at
+const STEP: f64 = 1.0 / 500.0;
+
+/// Hosts the bridge in an embedded Lua 5.1 with stand-ins for the DCS scripting
+/// environment (see `dcs.lua`), so scripts and the bridge can be run headless.
+///
+/// The runtime is process wide, so a process can only host one harness.
+pub struct Harness {
+    lua: Lua,
+    write_dir: PathBuf,
+}
+
+impl Harness {
+    /// Creates the Lua state with the DCS stand-ins installed and the `dcs_ts`
+    /// module loaded as `ts`, like `ts-init.lua` does within DCS.
+    pub fn new(write_dir: impl Into<PathBuf>) -> LuaResult<Self> {
+        let write_dir = write_dir.into();
+        for dir in ["Config", "Logs", "Data"] {
+            fs::create_dir_all(write_dir.join(dir)).map_err(LuaError::external)?;
+        }
+
+        let lua = Lua::new();
+        lua.load(include_str!("dcs.lua"))
+            .set_name("dcs.lua")?
+            .exec()?;
+        let ts = crate::dcs_ts(&lua)?;
+        lua.globals()
+            .get::<_, LuaTable>("package")?
+            .get::<_, LuaTable>("loaded")?
+            .set("dcs_ts", ts.clone())?;
+        lua.globals().set("ts", ts)?;
+
+        Ok(Self { lua, write_dir })
+    }
+
+    pub fn lua(&self) -> &Lua {
+        &self.lua
+    }
+
+    pub fn write_dir(&self) -> &Path {
+        &self.write_dir
+    }
+
+    /// Writes `Config/ts.json` of the write dir.
+    pub fn write_config(&self, config: &serde_json::Value) -> io::Result<()> {
+        fs::write(
+            self.write_dir.join("Config/ts.json"),
+            serde_json::to_vec_pretty(config)?,
+        )
+    }
+
+    /// Starts the runtime and loads the bridge, which schedules its polling loop.
+    pub fn initialize(&self) -> LuaResult<()> {
+        let ts: LuaTable = self.lua.globals().get("ts")?;
+        let mission = self.lua.create_table()?;
+        mission.set("theatre", "Caucasus")?;
+        mission.set("name", "harness")?;
+        ts.get::<_, LuaFunction>("initialize")?
+            .call::<_, LuaValue>((self.write_dir.to_string_lossy().to_string(), mission))?;
+        Ok(())
+    }
+
+    /// Advances mission time by `seconds`, running every scheduled function that
+    /// comes due (including the bridge polling for tasks).
+    pub fn advance(&self, seconds: f64) -> LuaResult<()> {
+        self.harness()?
+            .get::<_, LuaFunction>("advance")?
+            .call(seconds)
+    }
+
+    /// Keeps advancing mission time until `condition` holds, giving up after
+    /// `timeout` of wall clock time. Scripts run on their own threads, so every
+    /// step also yields to them.
+    pub fn run_until(
+        &self,
+        timeout: Duration,
+        mut condition: impl FnMut(&Self) -> LuaResult<bool>,
+    ) -> LuaResult<bool> {
+        let started = Instant::now();
+        while started.elapsed() < timeout {
+            self.advance(STEP)?;
+            if condition(self)? {
+                return Ok(true);
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        Ok(false)
+    }
+
+    /// Evaluates a Lua chunk within the harness, e.g. to script the mission with
+    /// `harness.addGroup`.
+    pub fn eval<'lua, T: FromLuaMulti<'lua>>(&'lua self, code: &str) -> LuaResult<T> {
+        self.lua.load(code).eval()
+    }
+
+    /// Delivers a mission event to every registered event handler.
+    pub fn dispatch_event(&self, event: LuaTable) -> LuaResult<()> {
+        self.harness()?
+            .get::<_, LuaFunction>("dispatchEvent")?
+            .call(event)
+    }
+
+    /// Returns the arguments of every recorded call of the stand-in `name` (e.g.
+    /// `trigger.action.outText`), in call order.
+    pub fn calls(&self, name: &str) -> LuaResult<Vec<LuaTable>> {
+        let mut result = vec![];
+        for call in self
+            .harness()?
+            .get::<_, LuaTable>("calls")?
+            .sequence_values::<LuaTable>()
+        {
+            let call = call?;
+            if call.get::<_, String>("name")? == name {
+                result.push(call.get("args")?);
+            }
+        }
+        Ok(result)
+    }
+
+    fn harness(&self) -> LuaResult<LuaTable> {
+        self.lua.globals().get("harness")
+    }
+}
@@ -0,0 +1,117 @@
+use std::{fs, time::Duration};
+
+use serde_json::json;
+
+use super::Harness;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+const SCRIPT: &str = r#"
+import { EventType, streamEvents } from "@dcs/event.ts";
+import { getGroupUnits } from "@dcs/group.ts";
+import { reload, stash, takeStash } from "@dcs/runtime.ts";
+import { createMark, outText } from "@dcs/trigger.ts";
+
+window.addEventListener("reload", () => stash("marks", [42]));
+
+window.onload = async () => {
+  const marks = takeStash<number[]>("marks");
+  if (marks !== null) {
+    const units = await getGroupUnits("Uzi");
+    await outText(`reloaded: ${units.length} units, marks ${marks.join(",")}`);
+    return;
+  }
+
+  const units = await getGroupUnits("Uzi");
+  await outText(`units: ${units.map((unit) => unit.name).join(",")}`);
+
+  for await (const event of streamEvents([EventType.BIRTH])) {
+    await outText(`birth: ${(event as any).initiator.unit.name}`);
+    break;
+  }
+
+  await createMark(42, "target", [1, 2, 0]);
+  reload();
+};
+"#;
+
+fn out_texts(harness: &Harness) -> Vec<String> {
+    harness
+        .calls("trigger.action.outText")
+        .unwrap()
+        .iter()
+        .map(|args| args.get(1).unwrap())
+        .collect()
+}
+
+// the runtime is process wide, so everything is exercised by a single test
+#[test]
+fn bridge_end_to_end() {
+    let write_dir = std::env::temp_dir().join(format!("dcs-ts-harness-{}", std::process::id()));
+    let harness = Harness::new(&write_dir).unwrap();
+    let script = write_dir.join("script.ts");
+    fs::write(&script, SCRIPT).unwrap();
+    harness
+        .write_config(&json!({
+            "development": false,
+            "debugging": true,
+            "scripts": [script],
+        }))
+        .unwrap();
+
+    harness
+        .eval::<()>(
+            r#"harness.addGroup({
+                name = "Uzi",
+                units = {{ name = "Uzi 1-1" }, { name = "Uzi 1-2" }},
+            })"#,
+        )
+        .unwrap();
+    harness.initialize().unwrap();
+
+    // task results are returned to the script
+    assert!(harness
+        .run_until(TIMEOUT, |harness| Ok(!out_texts(harness).is_empty()))
+        .unwrap());
+    assert_eq!(out_texts(&harness), vec!["units: Uzi 1-1,Uzi 1-2"]);
+
+    // mission events are streamed to the script through a channel
+    assert!(harness
+        .run_until(TIMEOUT, |harness| harness
+            .eval("return #harness.eventHandlers > 0"))
+        .unwrap());
+    harness
+        .eval::<()>(
+            r#"harness.dispatchEvent({
+                id = world.event.S_EVENT_BIRTH,
+                initiator = Unit.getByName("Uzi 1-2"),
+            })"#,
+        )
+        .unwrap();
+    assert!(harness
+        .run_until(TIMEOUT, |harness| Ok(out_texts(harness).len() == 2))
+        .unwrap());
+    assert_eq!(out_texts(&harness)[1], "birth: Uzi 1-2");
+
+    // a reload releases the lua resources of the old generation, and the tasks
+    //  of the new generation resolve
+    assert!(harness
+        .run_until(TIMEOUT, |harness| Ok(out_texts(harness).len() == 3))
+        .unwrap());
+    assert_eq!(out_texts(&harness)[2], "reloaded: 2 units, marks 42");
+    let removed: Vec<u32> = harness
+        .calls("trigger.action.removeMark")
+        .unwrap()
+        .iter()
+        .map(|args| args.get(1).unwrap())
+        .collect();
+    assert_eq!(removed, vec![42]);
+    assert_eq!(
+        harness
+            .eval::<u32>("return #harness.eventHandlers")
+            .unwrap(),
+        0
+    );
+
+    let _ = fs::remove_dir_all(&write_dir);
+}
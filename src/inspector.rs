@@ -0,0 +1,586 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    net::{SocketAddr, TcpListener as StdTcpListener},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use deno_core::{anyhow::Error, error::generic_error};
+use deno_runtime::inspector_server::InspectorServer;
+use once_cell::sync::Lazy;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{copy_bidirectional, AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    runtime,
+    sync::Notify,
+    time::sleep,
+};
+
+const MAX_REQUEST_HEAD_SIZE: usize = 8 * 1024;
+
+// how long a DevTools reconnect waits for a reloaded worker to register again
+const TARGET_WAIT_ATTEMPTS: usize = 50;
+const TARGET_WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+static TARGET_REGISTERED: Lazy<Notify> = Lazy::new(Notify::new);
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct InspectorConfig {
+    pub enabled: bool,
+    pub host: String,
+    pub port: u16,
+    pub token: Option<String>,
+}
+
+impl Default for InspectorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "127.0.0.1".to_string(),
+            port: 50009,
+            token: None,
+        }
+    }
+}
+
+impl InspectorConfig {
+    pub fn address(&self) -> Result<SocketAddr, Error> {
+        format!("{}:{}", self.host, self.port)
+            .parse()
+            .map_err(|e| generic_error(format!("invalid inspector address: {}", e)))
+    }
+}
+
+/// Starts the inspector server described by `config`. This is done once for the
+/// lifetime of the process, every worker registers with the returned server.
+///
+/// The deno inspector server is bound to an ephemeral loopback port and is never
+/// exposed directly. Instead a small gateway listens on the configured address,
+/// checks the optional shared-secret token on every request and forwards the
+/// connection to the real inspector.
+///
+/// The token only guards the gateway, it is not a security boundary on the machine
+/// running DCS: the deno inspector itself accepts any local connection, and its
+/// port is picked by binding and releasing an ephemeral port which another local
+/// process could grab before deno binds it. Expose the gateway beyond loopback only
+/// when every local process is trusted.
+///
+/// Deno hands out a new random id every time a worker registers, the gateway
+/// replaces those with an id derived from the module url of the worker. This keeps
+/// the DevTools websocket url stable across reloads so the frontend can reconnect.
+/// While a blue/green candidate boots, the old and the new worker share a stable id,
+/// it then resolves to the worker that registered last.
+pub fn start(config: &InspectorConfig) -> Result<Arc<InspectorServer>, Error> {
+    let address = config.address()?;
+    let listener = StdTcpListener::bind(address)?;
+    listener.set_nonblocking(true)?;
+
+    let inner_address = StdTcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let server = Arc::new(InspectorServer::new(inner_address, "DCSTS".to_string()));
+
+    let token = config.token.clone();
+    let registrations = Arc::new(Mutex::new(Registrations::default()));
+    thread::Builder::new()
+        .name("dcs-ts-inspector".to_string())
+        .spawn(move || {
+            let rt = runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            rt.block_on(async move {
+                let listener = match TcpListener::from_std(listener) {
+                    Ok(listener) => listener,
+                    Err(e) => {
+                        log::error!("failed to start inspector gateway: {}", e);
+                        return;
+                    }
+                };
+
+                let watched = registrations.clone();
+                tokio::spawn(async move {
+                    loop {
+                        TARGET_REGISTERED.notified().await;
+                        match fetch_targets(inner_address, &inner_address.to_string()).await {
+                            Ok(targets) => watched.lock().unwrap().record(&targets),
+                            Err(e) => log::debug!("failed to list inspector targets: {}", e),
+                        }
+                    }
+                });
+
+                loop {
+                    let (stream, peer) = match listener.accept().await {
+                        Ok(conn) => conn,
+                        Err(e) => {
+                            log::warn!("inspector gateway failed to accept: {}", e);
+                            continue;
+                        }
+                    };
+
+                    let token = token.clone();
+                    let registrations = registrations.clone();
+                    tokio::spawn(async move {
+                        let result =
+                            handle_connection(stream, inner_address, token, registrations).await;
+                        if let Err(e) = result {
+                            log::debug!("inspector connection from {} closed: {}", peer, e);
+                        }
+                    });
+                }
+            });
+        })?;
+
+    log::info!(
+        "inspector listening on {}{}",
+        address,
+        if config.token.is_some() {
+            " (token required)"
+        } else {
+            ""
+        }
+    );
+    Ok(server)
+}
+
+/// Tells the gateway that a worker registered with the inspector server, so that
+/// it can tell the registrations sharing a stable id apart.
+pub fn target_registered() {
+    TARGET_REGISTERED.notify_one();
+}
+
+/// Deno lists its targets in no particular order, the gateway numbers them in the
+/// order it first sees them.
+#[derive(Default)]
+struct Registrations {
+    next: u64,
+    seen: HashMap<String, u64>,
+}
+
+impl Registrations {
+    fn record(&mut self, targets: &[serde_json::Value]) {
+        let ids: Vec<&str> = targets
+            .iter()
+            .filter_map(|target| target.get("id")?.as_str())
+            .collect();
+        // deregistered targets are not listed anymore
+        self.seen.retain(|id, _| ids.contains(&id.as_str()));
+        for id in ids {
+            if !self.seen.contains_key(id) {
+                self.seen.insert(id.to_string(), self.next);
+                self.next += 1;
+            }
+        }
+    }
+
+    /// Returns the id deno assigned to the newest registration under `stable_id`.
+    fn newest(&self, targets: &[serde_json::Value], stable_id: &str) -> Option<String> {
+        targets
+            .iter()
+            .filter_map(|target| {
+                let url = target.get("url")?.as_str()?;
+                let id = target.get("id")?.as_str()?;
+                if target_id(url) == stable_id {
+                    Some((self.seen.get(id).copied().unwrap_or(u64::MAX), id))
+                } else {
+                    None
+                }
+            })
+            .max_by_key(|(order, _)| *order)
+            .map(|(_, id)| id.to_string())
+    }
+
+    /// Drops all but the newest registration of every stable id.
+    fn dedup(&self, targets: Vec<serde_json::Value>) -> Vec<serde_json::Value> {
+        let newest: Vec<String> = targets
+            .iter()
+            .filter_map(|target| self.newest(&targets, &target_id(target.get("url")?.as_str()?)))
+            .collect();
+        targets
+            .into_iter()
+            .filter(|target| match target.get("id").and_then(|id| id.as_str()) {
+                Some(id) if target.get("url").and_then(|url| url.as_str()).is_some() => {
+                    newest.iter().any(|n| n == id)
+                }
+                _ => true,
+            })
+            .collect()
+    }
+}
+
+struct RequestHead {
+    raw: Vec<u8>,
+    path: String,
+    query: Option<String>,
+    host: Option<String>,
+}
+
+async fn read_request_head(stream: &mut TcpStream) -> Result<RequestHead, Error> {
+    let mut raw = Vec::new();
+    let mut buf = [0u8; 1024];
+    let head_end = loop {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Err(generic_error("connection closed before request head"));
+        }
+        raw.extend_from_slice(&buf[..n]);
+        if let Some(end) = find_head_end(&raw) {
+            break end;
+        }
+        if raw.len() > MAX_REQUEST_HEAD_SIZE {
+            return Err(generic_error("request head too large"));
+        }
+    };
+
+    let head = String::from_utf8_lossy(&raw[..head_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let target = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .ok_or_else(|| generic_error("malformed request line"))?;
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), Some(query.to_string())),
+        None => (target.to_string(), None),
+    };
+    let host = lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.eq_ignore_ascii_case("host") {
+            Some(value.trim().to_string())
+        } else {
+            None
+        }
+    });
+
+    Ok(RequestHead {
+        raw,
+        path,
+        query,
+        host,
+    })
+}
+
+fn find_head_end(data: &[u8]) -> Option<usize> {
+    data.windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+}
+
+fn query_param<'a>(query: &'a Option<String>, name: &str) -> Option<&'a str> {
+    query.as_deref()?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        if key == name {
+            Some(value)
+        } else {
+            None
+        }
+    })
+}
+
+// percent-encodes everything but the unreserved characters of RFC 3986
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut bytes = value.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = [bytes.next()?, bytes.next()?];
+            decoded.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            decoded.push(byte);
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn token_matches(expected: &str, provided: Option<&str>) -> bool {
+    let provided = match provided {
+        Some(provided) => provided.as_bytes(),
+        None => return false,
+    };
+    let expected = expected.as_bytes();
+    if provided.len() != expected.len() {
+        return false;
+    }
+    expected
+        .iter()
+        .zip(provided)
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+        == 0
+}
+
+async fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> Result<(), Error> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    Ok(())
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    inner_address: SocketAddr,
+    token: Option<String>,
+    registrations: Arc<Mutex<Registrations>>,
+) -> Result<(), Error> {
+    let head = read_request_head(&mut stream).await?;
+
+    if let Some(token) = &token {
+        let provided = query_param(&head.query, "token").and_then(percent_decode);
+        if !token_matches(token, provided.as_deref()) {
+            log::warn!("rejected inspector request without a valid token");
+            return write_response(&mut stream, "401 Unauthorized", "text/plain", b"").await;
+        }
+    }
+
+    let host = head
+        .host
+        .clone()
+        .unwrap_or_else(|| inner_address.to_string());
+
+    if head.path == "/json" || head.path == "/json/list" {
+        let targets = fetch_targets(inner_address, &host).await?;
+        let targets = {
+            let mut registrations = registrations.lock().unwrap();
+            registrations.record(&targets);
+            registrations.dedup(targets)
+        };
+        let targets = rewrite_targets(targets, &token);
+        let body = serde_json::to_vec(&targets)?;
+        return write_response(&mut stream, "200 OK", "application/json", &body).await;
+    }
+
+    let mut raw = head.raw;
+    if let Some(stable_id) = head.path.strip_prefix("/ws/") {
+        let uuid = match resolve_target(inner_address, &host, stable_id, &registrations).await? {
+            Some(uuid) => uuid,
+            None => {
+                return write_response(&mut stream, "404 Not Found", "text/plain", b"").await;
+            }
+        };
+        raw = replace_first(
+            &raw,
+            format!("/ws/{}", stable_id).as_bytes(),
+            format!("/ws/{}", uuid).as_bytes(),
+        );
+    }
+
+    let mut inner = TcpStream::connect(inner_address).await?;
+    inner.write_all(&raw).await?;
+    copy_bidirectional(&mut stream, &mut inner).await?;
+    Ok(())
+}
+
+/// Returns the stable target id for a worker registered with `url`.
+pub fn target_id(url: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    url.hash(&mut hasher);
+    format!("dcs-ts-{:016x}", hasher.finish())
+}
+
+/// Finds the id deno assigned to the worker currently registered under
+/// `stable_id`, waiting for a bit in case the worker is in the middle of a reload.
+async fn resolve_target(
+    inner_address: SocketAddr,
+    host: &str,
+    stable_id: &str,
+    registrations: &Mutex<Registrations>,
+) -> Result<Option<String>, Error> {
+    for _ in 0..TARGET_WAIT_ATTEMPTS {
+        let targets = fetch_targets(inner_address, host).await?;
+        let found = {
+            let mut registrations = registrations.lock().unwrap();
+            registrations.record(&targets);
+            registrations.newest(&targets, stable_id)
+        };
+        if found.is_some() {
+            return Ok(found);
+        }
+        sleep(TARGET_WAIT_INTERVAL).await;
+    }
+    Ok(None)
+}
+
+fn replace_first(data: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+    match data.windows(from.len()).position(|window| window == from) {
+        Some(pos) => [&data[..pos], to, &data[pos + from.len()..]].concat(),
+        None => data.to_vec(),
+    }
+}
+
+async fn fetch_targets(
+    inner_address: SocketAddr,
+    host: &str,
+) -> Result<Vec<serde_json::Value>, Error> {
+    let mut stream = TcpStream::connect(inner_address).await?;
+    let request = format!(
+        "GET /json/list HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        host
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    let body_start =
+        find_head_end(&response).ok_or_else(|| generic_error("malformed inspector response"))?;
+    Ok(serde_json::from_slice(&response[body_start..])?)
+}
+
+/// Swaps deno's target ids for stable ones and adds the access token to the
+/// websocket urls handed out to DevTools, so that the frontend can connect back
+/// through the gateway.
+fn rewrite_targets(
+    mut targets: Vec<serde_json::Value>,
+    token: &Option<String>,
+) -> Vec<serde_json::Value> {
+    for target in targets.iter_mut() {
+        let (uuid, url) = match (
+            target.get("id").and_then(|v| v.as_str()),
+            target.get("url").and_then(|v| v.as_str()),
+        ) {
+            (Some(uuid), Some(url)) => (uuid.to_string(), url.to_string()),
+            _ => continue,
+        };
+        let stable_id = target_id(&url);
+        target["id"] = serde_json::Value::String(stable_id.clone());
+
+        for key in ["webSocketDebuggerUrl", "devtoolsFrontendUrl"] {
+            if let Some(serde_json::Value::String(url)) = target.get_mut(key) {
+                *url = url.replace(&format!("/ws/{}", uuid), &format!("/ws/{}", stable_id));
+                if let Some(token) = token {
+                    *url = match key {
+                        "devtoolsFrontendUrl" => nest_token(url, token),
+                        _ => format!("{}?token={}", url, percent_encode(token)),
+                    };
+                }
+            }
+        }
+    }
+    targets
+}
+
+// the frontend url carries the websocket url (sans scheme) in its `ws` param, the
+//  token has to go within that param so it is encoded once more
+fn nest_token(url: &str, token: &str) -> String {
+    let start = match url.find("?ws=").or_else(|| url.find("&ws=")) {
+        Some(start) => start + "?ws=".len(),
+        None => return url.to_string(),
+    };
+    let end = url[start..].find('&').map_or(url.len(), |end| start + end);
+    format!(
+        "{}{}{}",
+        &url[..end],
+        percent_encode(&format!("?token={}", percent_encode(token))),
+        &url[end..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_must_match_exactly() {
+        assert!(token_matches("s3cret", Some("s3cret")));
+        assert!(!token_matches("s3cret", Some("s3creT")));
+        assert!(!token_matches("s3cret", Some("s3cre")));
+        assert!(!token_matches("s3cret", Some("s3crets")));
+        assert!(!token_matches("s3cret", Some("")));
+        assert!(!token_matches("s3cret", None));
+    }
+
+    #[test]
+    fn token_survives_percent_encoding() {
+        let token = "a b&c=d/é";
+        let encoded = percent_encode(token);
+        assert_eq!(encoded, "a%20b%26c%3Dd%2F%C3%A9");
+        assert_eq!(percent_decode(&encoded).as_deref(), Some(token));
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(percent_decode("%zz"), None);
+    }
+
+    #[test]
+    fn token_is_nested_within_the_ws_param() {
+        assert_eq!(
+            nest_token(
+                "devtools://devtools/bundled/js_app.html?experiments=true&ws=127.0.0.1:9229/ws/1",
+                "a&b"
+            ),
+            "devtools://devtools/bundled/js_app.html?experiments=true&ws=127.0.0.1:9229/ws/1%3Ftoken%3Da%2526b"
+        );
+        assert_eq!(
+            nest_token("devtools://x.html?ws=host/ws/1&v8only=true", "t"),
+            "devtools://x.html?ws=host/ws/1%3Ftoken%3Dt&v8only=true"
+        );
+        assert_eq!(nest_token("devtools://x.html", "t"), "devtools://x.html");
+    }
+
+    fn target(id: &str, url: &str) -> serde_json::Value {
+        serde_json::json!({ "id": id, "url": url })
+    }
+
+    #[test]
+    fn the_newest_registration_of_a_target_wins() {
+        let mut registrations = Registrations::default();
+        let stable_id = target_id("file:///main.ts");
+        registrations.record(&[target("old", "file:///main.ts")]);
+        let targets = vec![
+            target("new", "file:///main.ts"),
+            target("old", "file:///main.ts"),
+            target("other", "file:///other.ts"),
+        ];
+        registrations.record(&targets);
+        assert_eq!(
+            registrations.newest(&targets, &stable_id).as_deref(),
+            Some("new")
+        );
+
+        let ids: Vec<_> = registrations
+            .dedup(targets)
+            .iter()
+            .map(|target| target["id"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(ids, vec!["new", "other"]);
+    }
+
+    #[test]
+    fn deregistered_targets_are_forgotten() {
+        let mut registrations = Registrations::default();
+        registrations.record(&[target("a", "file:///main.ts")]);
+        registrations.record(&[target("b", "file:///main.ts")]);
+        assert!(!registrations.seen.contains_key("a"));
+        // a target not seen before counts as the newest
+        let targets = vec![
+            target("b", "file:///main.ts"),
+            target("c", "file:///main.ts"),
+        ];
+        assert_eq!(
+            registrations
+                .newest(&targets, &target_id("file:///main.ts"))
+                .as_deref(),
+            Some("c")
+        );
+    }
+}
@@ -0,0 +1,509 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use deno_core::anyhow::Error;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::{DcsBackend, Reply},
+    config::MissionInfo,
+    runner,
+    runtime::{Task, TaskResult, TaskResultValue},
+};
+
+// how long a replay waits for the scripts to queue the next recorded task
+const REPLAY_TASK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct JournalConfig {
+    /// Records every task request, task result and channel message, in either
+    /// direction, into `Logs/dcs-ts-journal-<unix time>.jsonl.gz`.
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum JournalEvent {
+    /// First record of every journal, replays boot the scripts for its mission.
+    Session {
+        mission: Option<MissionInfo>,
+    },
+    #[serde(rename_all = "camelCase")]
+    TaskRequest {
+        id: u64,
+        worker: String,
+        target: String,
+        args: Option<serde_json::Value>,
+    },
+    TaskResult {
+        id: u64,
+        result: TaskResultValue,
+    },
+    /// Message Lua sent to the scripts.
+    ChannelMessage {
+        channel: u64,
+        message: serde_json::Value,
+    },
+    /// Message the scripts sent to Lua.
+    ChannelSend {
+        channel: u64,
+        message: serde_json::Value,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalRecord {
+    /// Wall clock time in milliseconds since the unix epoch.
+    pub time: u64,
+    /// Mission time as of the last bridge poll.
+    pub sim_time: Option<f64>,
+    #[serde(flatten)]
+    pub event: JournalEvent,
+}
+
+/// Appends records to a gzip compressed JSON lines file from a background thread,
+/// so recording never blocks the bridge.
+pub struct Journal {
+    tx: mpsc::Sender<JournalRecord>,
+}
+
+impl Journal {
+    pub fn create(write_dir: &str) -> Result<Self, Error> {
+        let started = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut path = PathBuf::from(write_dir);
+        path.push(format!("Logs/dcs-ts-journal-{}.jsonl.gz", started));
+        let file = File::create(&path)?;
+        log::info!("recording journal to {}", path.display());
+
+        let (tx, rx) = mpsc::channel::<JournalRecord>();
+        thread::Builder::new()
+            .name("dcs-ts-journal".to_string())
+            .spawn(move || {
+                let mut writer = GzEncoder::new(BufWriter::new(file), Compression::default());
+                while let Ok(record) = rx.recv() {
+                    let mut result = serde_json::to_writer(&mut writer, &record)
+                        .map_err(Error::from)
+                        .and_then(|_| Ok(writer.write_all(b"\n")?));
+                    // flushing only once caught up keeps the stream readable up to
+                    //  the last record if DCS crashes, without flushing every record
+                    if result.is_ok() && matches!(rx.try_recv(), Err(mpsc::TryRecvError::Empty)) {
+                        result = writer.flush().map_err(Error::from);
+                    }
+                    if let Err(e) = result {
+                        log::error!("failed to write journal record: {}", e);
+                    }
+                }
+                if let Err(e) = writer.finish() {
+                    log::error!("failed to finish journal: {}", e);
+                }
+            })?;
+
+        Ok(Self { tx })
+    }
+
+    pub fn record(&self, sim_time: Option<f64>, event: JournalEvent) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|it| it.as_millis() as u64)
+            .unwrap_or(0);
+        let _ = self.tx.send(JournalRecord {
+            time,
+            sim_time,
+            event,
+        });
+    }
+}
+
+/// Reads a journal, stopping at the first incomplete record of a journal cut off
+/// by a crash.
+pub fn read(path: &Path) -> Result<Vec<JournalRecord>, Error> {
+    let reader = BufReader::new(GzDecoder::new(File::open(path)?));
+    let mut records = vec![];
+    for line in reader.lines() {
+        let record = line
+            .map_err(Error::from)
+            .and_then(|line| Ok(serde_json::from_str(&line)?));
+        match record {
+            Ok(record) => records.push(record),
+            Err(e) => {
+                log::warn!("journal ends with an incomplete record: {}", e);
+                break;
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// Outcome of replaying a journal.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayReport {
+    pub records: usize,
+    pub replayed: usize,
+    /// Description of where the scripts diverged from the recording, if they did.
+    pub divergence: Option<String>,
+}
+
+// channel ids are handed to Lua within task args (`args.channel.id`), matching
+//  them up maps recorded channels onto the channels of the replay
+fn map_channels(
+    recorded: &serde_json::Value,
+    replayed: &serde_json::Value,
+    channels: &mut HashMap<u64, u64>,
+) {
+    match (recorded, replayed) {
+        (serde_json::Value::Object(recorded), serde_json::Value::Object(replayed)) => {
+            if let (Some(from), Some(to)) = (
+                recorded.get("channel").and_then(|it| it.get("id")),
+                replayed.get("channel").and_then(|it| it.get("id")),
+            ) {
+                if let (Some(from), Some(to)) = (from.as_u64(), to.as_u64()) {
+                    channels.insert(from, to);
+                }
+            }
+            for (key, value) in recorded {
+                if let Some(other) = replayed.get(key) {
+                    map_channels(value, other, channels);
+                }
+            }
+        }
+        (serde_json::Value::Array(recorded), serde_json::Value::Array(replayed)) => {
+            for (value, other) in recorded.iter().zip(replayed) {
+                map_channels(value, other, channels);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Answers tasks with the results recorded in a journal. A task is matched to the
+/// first unanswered recorded task of the same worker and target. Results and
+/// channel messages are handed back in recorded order, each once every task
+/// recorded before it was matched.
+pub struct JournalBackend {
+    records: Vec<JournalRecord>,
+    // index of the first record not fed to the scripts yet
+    cursor: usize,
+    // indices of recorded task requests already matched
+    matched: HashSet<usize>,
+    // recorded task id -> id of the replayed task waiting for its result
+    pending: HashMap<u64, u64>,
+    // recorded channel id -> channel id of the replay
+    channels: HashMap<u64, u64>,
+    outbox: Vec<Reply>,
+    divergence: Option<String>,
+}
+
+impl JournalBackend {
+    pub fn new(records: Vec<JournalRecord>) -> Self {
+        Self {
+            records,
+            cursor: 0,
+            matched: HashSet::new(),
+            pending: HashMap::new(),
+            channels: HashMap::new(),
+            outbox: vec![],
+            divergence: None,
+        }
+    }
+
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Ok(Self::new(read(path)?))
+    }
+
+    /// Mission the journal was recorded for.
+    pub fn mission(&self) -> Option<MissionInfo> {
+        self.records.iter().find_map(|record| match &record.event {
+            JournalEvent::Session { mission } => mission.clone(),
+            _ => None,
+        })
+    }
+
+    pub fn report(&self) -> ReplayReport {
+        let divergence = self.divergence.clone().or_else(|| {
+            self.records
+                .get(self.cursor)
+                .map(|record| match &record.event {
+                    JournalEvent::TaskRequest {
+                        id, worker, target, ..
+                    } => format!(
+                        "worker {} never queued task {} (recorded as task {})",
+                        worker, target, id
+                    ),
+                    _ => format!("stalled at record {}", self.cursor),
+                })
+        });
+        ReplayReport {
+            records: self.records.len(),
+            replayed: self.cursor,
+            divergence,
+        }
+    }
+
+    // moves past every record which was fed to the scripts, queueing the results
+    //  and channel messages on the way
+    fn advance(&mut self) {
+        while let Some(record) = self.records.get(self.cursor) {
+            match &record.event {
+                JournalEvent::TaskRequest { .. } if !self.matched.contains(&self.cursor) => break,
+                JournalEvent::TaskResult { id, result } => {
+                    if let Some(id) = self.pending.remove(id) {
+                        self.outbox.push(Reply::Result(TaskResult {
+                            id,
+                            result: result.clone(),
+                        }));
+                    }
+                }
+                JournalEvent::ChannelMessage { channel, message } => {
+                    let channel = self.channels.get(channel).copied().unwrap_or(*channel);
+                    self.outbox.push(Reply::Message {
+                        channel,
+                        message: message.clone(),
+                    });
+                }
+                // sends of the scripts are made again by the replayed scripts
+                _ => {}
+            }
+            self.cursor += 1;
+        }
+    }
+}
+
+impl DcsBackend for JournalBackend {
+    fn submit(&mut self, task: Task) {
+        if self.divergence.is_some() {
+            return;
+        }
+
+        let index = (self.cursor..self.records.len()).find(|index| {
+            !self.matched.contains(index)
+                && matches!(&self.records[*index].event, JournalEvent::TaskRequest { worker, target, .. }
+                    if *worker == task.worker && *target == task.target)
+        });
+        let index = match index {
+            Some(index) => index,
+            None => {
+                self.divergence = Some(format!(
+                    "worker {} queued task {} which is not in the journal",
+                    task.worker, task.target
+                ));
+                return;
+            }
+        };
+        self.matched.insert(index);
+
+        let (id, args) = match &self.records[index].event {
+            JournalEvent::TaskRequest { id, args, .. } => (*id, args.clone()),
+            _ => unreachable!(),
+        };
+        if let (Some(recorded), Some(replayed)) = (&args, &task.args) {
+            if recorded != replayed {
+                log::debug!(
+                    "args of task {} differ from the recording: {} != {}",
+                    task.target,
+                    replayed,
+                    recorded
+                );
+            }
+            map_channels(recorded, replayed, &mut self.channels);
+        }
+        // the result is held until the replay reaches it, tasks still running
+        //  when the recording ended stay unanswered
+        self.pending.insert(id, task.id);
+    }
+
+    fn take_replies(&mut self) -> Vec<Reply> {
+        self.advance();
+        std::mem::take(&mut self.outbox)
+    }
+
+    fn finished(&self) -> bool {
+        self.divergence.is_some() || self.cursor == self.records.len()
+    }
+}
+
+/// Feeds the recorded Lua side of a journal to the running scripts, in recorded
+/// order, until the scripts diverge from the recording or stop queueing the
+/// recorded tasks.
+pub fn replay(backend: &mut JournalBackend) -> Result<ReplayReport, Error> {
+    runner::run(backend, Some(REPLAY_TASK_TIMEOUT))?;
+    Ok(backend.report())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn record(event: JournalEvent) -> JournalRecord {
+        JournalRecord {
+            time: 0,
+            sim_time: None,
+            event,
+        }
+    }
+
+    fn request(id: u64, target: &str, args: Option<serde_json::Value>) -> JournalRecord {
+        record(JournalEvent::TaskRequest {
+            id,
+            worker: "main".to_string(),
+            target: target.to_string(),
+            args,
+        })
+    }
+
+    fn result(id: u64, value: serde_json::Value) -> JournalRecord {
+        record(JournalEvent::TaskResult {
+            id,
+            result: TaskResultValue::Ok(Some(value)),
+        })
+    }
+
+    fn message(channel: u64, message: serde_json::Value) -> JournalRecord {
+        record(JournalEvent::ChannelMessage { channel, message })
+    }
+
+    fn task(id: u64, target: &str, args: Option<serde_json::Value>) -> Task {
+        Task {
+            id,
+            worker: "main".to_string(),
+            generation: 1,
+            target: target.to_string(),
+            args,
+        }
+    }
+
+    // replies as (task id or channel, value) pairs, results keyed "task"
+    fn replies(backend: &mut JournalBackend) -> Vec<(&'static str, u64, serde_json::Value)> {
+        backend
+            .take_replies()
+            .into_iter()
+            .map(|reply| match reply {
+                Reply::Result(TaskResult {
+                    id,
+                    result: TaskResultValue::Ok(value),
+                }) => ("task", id, value.unwrap_or_default()),
+                Reply::Result(TaskResult {
+                    id,
+                    result: TaskResultValue::Error(e),
+                }) => ("task", id, json!(e)),
+                Reply::Message { channel, message } => ("channel", channel, message),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn channels_are_mapped_by_their_place_in_the_args() {
+        let mut channels = HashMap::new();
+        map_channels(
+            &json!({ "channel": { "id": 3 }, "nested": [{ "channel": { "id": 4 } }] }),
+            &json!({ "channel": { "id": 7 }, "nested": [{ "channel": { "id": 9 } }] }),
+            &mut channels,
+        );
+        assert_eq!(channels, HashMap::from([(3, 7), (4, 9)]));
+
+        // args not shaped alike map nothing
+        let mut channels = HashMap::new();
+        map_channels(
+            &json!({ "channel": { "id": 3 } }),
+            &json!({ "channel": 7, "other": { "channel": { "id": 9 } } }),
+            &mut channels,
+        );
+        assert!(channels.is_empty());
+    }
+
+    #[test]
+    fn replies_are_held_until_the_replay_reaches_them() {
+        let mut backend = JournalBackend::new(vec![
+            request(
+                1,
+                "createEventProducer",
+                Some(json!({ "channel": { "id": 3 } })),
+            ),
+            message(3, json!("before")),
+            request(2, "getTime", None),
+            result(1, json!(null)),
+            message(3, json!("after")),
+            result(2, json!(3600)),
+        ]);
+
+        backend.submit(task(
+            10,
+            "createEventProducer",
+            Some(json!({ "channel": { "id": 8 } })),
+        ));
+        // the result of task 1 was recorded after task 2 was queued
+        assert_eq!(replies(&mut backend), vec![("channel", 8, json!("before"))]);
+        assert!(!backend.finished());
+
+        backend.submit(task(11, "getTime", None));
+        assert_eq!(
+            replies(&mut backend),
+            vec![
+                ("task", 10, json!(null)),
+                ("channel", 8, json!("after")),
+                ("task", 11, json!(3600)),
+            ]
+        );
+        assert!(backend.finished());
+        assert!(backend.report().divergence.is_none());
+    }
+
+    #[test]
+    fn tasks_match_the_first_unanswered_request_of_their_target() {
+        let mut backend = JournalBackend::new(vec![
+            request(1, "getTime", None),
+            request(2, "getTime", None),
+            result(2, json!(2)),
+            result(1, json!(1)),
+        ]);
+
+        backend.submit(task(10, "getTime", None));
+        backend.submit(task(11, "getTime", None));
+        assert_eq!(
+            replies(&mut backend),
+            vec![("task", 11, json!(2)), ("task", 10, json!(1))]
+        );
+    }
+
+    #[test]
+    fn unrecorded_tasks_diverge() {
+        let mut backend =
+            JournalBackend::new(vec![request(1, "getTime", None), result(1, json!(3600))]);
+
+        backend.submit(task(10, "unitGetByName", None));
+        assert!(backend.finished());
+        assert!(replies(&mut backend).is_empty());
+        assert_eq!(
+            backend.report().divergence.unwrap(),
+            "worker main queued task unitGetByName which is not in the journal"
+        );
+    }
+
+    #[test]
+    fn tasks_never_queued_are_reported() {
+        let mut backend =
+            JournalBackend::new(vec![request(1, "getTime", None), result(1, json!(3600))]);
+
+        assert!(replies(&mut backend).is_empty());
+        assert!(!backend.finished());
+        let report = backend.report();
+        assert_eq!(report.replayed, 0);
+        assert_eq!(
+            report.divergence.unwrap(),
+            "worker main never queued task getTime (recorded as task 1)"
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use deno_core::{anyhow::Error, error::generic_error};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// A persistent key-value store of JSON values, kept in `Data/kv.sqlite3` of the
+/// write dir and shared by all workers.
+///
+/// Every write is committed to disk before it completes. SQLite's journal rolls
+/// back a write cut off by a crash on the next open, so the store never ends up
+/// corrupted. The connection is only used from the blocking thread pool.
+#[derive(Clone)]
+pub struct KvStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KvEntry {
+    pub key: String,
+    pub value: serde_json::Value,
+}
+
+/// A write within an atomic batch.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum KvMutation {
+    Set {
+        key: String,
+        value: serde_json::Value,
+    },
+    Delete {
+        key: String,
+    },
+}
+
+/// A precondition of an atomic batch. Without `value` the key has to be absent,
+/// a `value` of null requires a stored null.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KvCheck {
+    pub key: String,
+    #[serde(default, deserialize_with = "present")]
+    pub value: Option<serde_json::Value>,
+}
+
+// keeps a given null as `Some(Null)`, only a missing field is `None`
+fn present<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<serde_json::Value>, D::Error> {
+    serde_json::Value::deserialize(deserializer).map(Some)
+}
+
+fn decode(value: &str) -> Result<serde_json::Value, Error> {
+    Ok(serde_json::from_str(value)?)
+}
+
+impl KvStore {
+    pub fn open(write_dir: &str) -> Result<Self, Error> {
+        let mut path = PathBuf::from(write_dir);
+        path.push("Data/kv.sqlite3");
+        let to_error =
+            |e: rusqlite::Error| generic_error(format!("failed to open {}: {}", path.display(), e));
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let connection = Connection::open(&path).map_err(to_error)?;
+        connection
+            .execute_batch(
+                "PRAGMA journal_mode = WAL;
+                 PRAGMA synchronous = FULL;
+                 CREATE TABLE IF NOT EXISTS kv (key TEXT PRIMARY KEY, value TEXT NOT NULL) WITHOUT ROWID;",
+            )
+            .map_err(to_error)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<serde_json::Value>, Error> {
+        let connection = self.connection.lock().unwrap();
+        get(&connection, key)?
+            .map(|value| decode(&value))
+            .transpose()
+    }
+
+    pub fn set(&self, key: &str, value: &serde_json::Value) -> Result<(), Error> {
+        let connection = self.connection.lock().unwrap();
+        set(&connection, key, value)
+    }
+
+    pub fn delete(&self, key: &str) -> Result<(), Error> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute("DELETE FROM kv WHERE key = ?1", params![key])?;
+        Ok(())
+    }
+
+    /// Returns the entries whose key starts with `prefix` in key order.
+    pub fn list(&self, prefix: &str, limit: Option<usize>) -> Result<Vec<KvEntry>, Error> {
+        let connection = self.connection.lock().unwrap();
+        // keys compare bytewise, so the keys with the prefix follow it directly
+        let mut statement =
+            connection.prepare_cached("SELECT key, value FROM kv WHERE key >= ?1 ORDER BY key")?;
+        let mut rows = statement.query(params![prefix])?;
+        let mut entries = vec![];
+        while let Some(row) = rows.next()? {
+            if entries.len() >= limit.unwrap_or(usize::MAX) {
+                break;
+            }
+            let key: String = row.get(0)?;
+            if !key.starts_with(prefix) {
+                break;
+            }
+            let value: String = row.get(1)?;
+            entries.push(KvEntry {
+                key,
+                value: decode(&value)?,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Applies all `mutations` at once if every check holds, returning whether
+    /// the batch was committed.
+    pub fn atomic(&self, checks: &[KvCheck], mutations: &[KvMutation]) -> Result<bool, Error> {
+        let mut connection = self.connection.lock().unwrap();
+        // takes the write lock up front, so nothing changes between the checks and
+        //  the writes
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        for check in checks {
+            let current = get(&transaction, &check.key)?
+                .map(|value| serde_json::from_str::<serde_json::Value>(&value).ok());
+            let matches = match (&current, &check.value) {
+                (None, None) => true,
+                (Some(Some(current)), Some(expected)) => current == expected,
+                _ => false,
+            };
+            if !matches {
+                return Ok(false);
+            }
+        }
+        for mutation in mutations {
+            match mutation {
+                KvMutation::Set { key, value } => set(&transaction, key, value)?,
+                KvMutation::Delete { key } => {
+                    transaction.execute("DELETE FROM kv WHERE key = ?1", params![key])?;
+                }
+            }
+        }
+        transaction.commit()?;
+        Ok(true)
+    }
+}
+
+fn get(connection: &Connection, key: &str) -> Result<Option<String>, Error> {
+    Ok(connection
+        .query_row("SELECT value FROM kv WHERE key = ?1", params![key], |row| {
+            row.get(0)
+        })
+        .optional()?)
+}
+
+fn set(connection: &Connection, key: &str, value: &serde_json::Value) -> Result<(), Error> {
+    connection.execute(
+        "INSERT OR REPLACE INTO kv (key, value) VALUES (?1, ?2)",
+        params![key, serde_json::to_string(value)?],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn store(name: &str) -> KvStore {
+        let dir = std::env::temp_dir().join(format!("dcs-ts-kv-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        KvStore::open(dir.to_str().unwrap()).unwrap()
+    }
+
+    fn keys(entries: Vec<KvEntry>) -> Vec<String> {
+        entries.into_iter().map(|entry| entry.key).collect()
+    }
+
+    fn check(check: serde_json::Value) -> KvCheck {
+        serde_json::from_value(check).unwrap()
+    }
+
+    #[test]
+    fn values_are_set_replaced_and_deleted() {
+        let kv = store("values");
+        assert_eq!(kv.get("a").unwrap(), None);
+        kv.set("a", &json!({ "credits": 1 })).unwrap();
+        assert_eq!(kv.get("a").unwrap(), Some(json!({ "credits": 1 })));
+        kv.set("a", &json!([1, 2])).unwrap();
+        assert_eq!(kv.get("a").unwrap(), Some(json!([1, 2])));
+        kv.delete("a").unwrap();
+        assert_eq!(kv.get("a").unwrap(), None);
+        // deleting a missing key is not an error
+        kv.delete("a").unwrap();
+    }
+
+    #[test]
+    fn list_takes_prefixes_literally() {
+        let kv = store("literal");
+        for key in ["a%b", "a%c", "a_b", "axb", "ab"] {
+            kv.set(key, &json!(key)).unwrap();
+        }
+        assert_eq!(keys(kv.list("a%", None).unwrap()), vec!["a%b", "a%c"]);
+        assert_eq!(keys(kv.list("a_", None).unwrap()), vec!["a_b"]);
+        assert_eq!(kv.list("a%b", None).unwrap()[0].value, json!("a%b"));
+    }
+
+    #[test]
+    fn list_stops_at_the_end_of_the_prefix() {
+        let kv = store("bounds");
+        for key in ["b", "ab\u{10ffff}", "a", "ac", "ab", "ab/1", "aa"] {
+            kv.set(key, &json!(null)).unwrap();
+        }
+        assert_eq!(
+            keys(kv.list("ab", None).unwrap()),
+            vec!["ab", "ab/1", "ab\u{10ffff}"]
+        );
+        assert_eq!(
+            keys(kv.list("", None).unwrap()),
+            vec!["a", "aa", "ab", "ab/1", "ab\u{10ffff}", "ac", "b"]
+        );
+        assert_eq!(keys(kv.list("a", Some(2)).unwrap()), vec!["a", "aa"]);
+        assert!(kv.list("ab\u{10ffff}/", None).unwrap().is_empty());
+        assert!(kv.list("c", None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn stored_nulls_are_told_apart_from_missing_keys() {
+        let kv = store("null");
+        kv.set("stored", &json!(null)).unwrap();
+        assert_eq!(kv.get("stored").unwrap(), Some(json!(null)));
+        assert_eq!(kv.get("missing").unwrap(), None);
+
+        let absent = |key| check(json!({ "key": key }));
+        let null = |key| check(json!({ "key": key, "value": null }));
+        assert!(!kv.atomic(&[absent("stored")], &[]).unwrap());
+        assert!(kv.atomic(&[null("stored")], &[]).unwrap());
+        assert!(!kv.atomic(&[null("missing")], &[]).unwrap());
+        assert!(kv.atomic(&[absent("missing")], &[]).unwrap());
+    }
+
+    #[test]
+    fn atomic_batches_apply_all_or_nothing() {
+        let kv = store("atomic");
+        kv.set("balance", &json!(10)).unwrap();
+        let mutations = [
+            KvMutation::Set {
+                key: "balance".to_string(),
+                value: json!(5),
+            },
+            KvMutation::Delete {
+                key: "pending".to_string(),
+            },
+            KvMutation::Set {
+                key: "spent".to_string(),
+                value: json!(5),
+            },
+        ];
+        kv.set("pending", &json!(true)).unwrap();
+
+        let stale = check(json!({ "key": "balance", "value": 9 }));
+        assert!(!kv.atomic(&[stale], &mutations).unwrap());
+        assert_eq!(kv.get("balance").unwrap(), Some(json!(10)));
+        assert_eq!(kv.get("pending").unwrap(), Some(json!(true)));
+        assert_eq!(kv.get("spent").unwrap(), None);
+
+        let current = check(json!({ "key": "balance", "value": 10 }));
+        assert!(kv.atomic(&[current], &mutations).unwrap());
+        assert_eq!(kv.get("balance").unwrap(), Some(json!(5)));
+        assert_eq!(kv.get("pending").unwrap(), None);
+        assert_eq!(kv.get("spent").unwrap(), Some(json!(5)));
+    }
+}
@@ -1,20 +1,39 @@
 #![feature(backtrace)]
 
+pub mod backend;
+mod config;
+mod env;
+#[cfg(feature = "harness")]
+pub mod harness;
+mod inspector;
+mod journal;
+mod kv;
+mod loader;
+mod logging;
+mod permissions;
+mod remote;
+pub mod runner;
 mod runtime;
+mod sdk;
+mod source_maps;
+mod sqlite;
+mod stash;
 
+use backend::QueueBackend;
+use deno_core::{anyhow::Error as AnyError, error::generic_error};
 use mlua::prelude::*;
 use mlua::Value;
 use once_cell::sync::Lazy;
-use runtime::TaskResult;
-use runtime::{Config, Runtime};
-use std::fs::File;
-use std::io::BufReader;
-use std::path::PathBuf;
+use runner::JournalBackend;
+use runtime::Runtime;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
 use thiserror::Error;
 
-use crate::runtime::TaskResultValue;
+pub use backend::DcsBackend;
+pub use config::{Config, ConfigError, MissionInfo};
+pub use runtime::{Task, TaskResult, TaskResultValue};
 
 static INITIALIZED: Lazy<AtomicBool> = Lazy::new(|| AtomicBool::new(false));
 static RUNTIME: Lazy<Mutex<Option<Runtime>>> = Lazy::new(|| Mutex::new(None));
@@ -27,46 +46,73 @@ pub fn init(config: &Config) {
         return;
     }
 
-    use log::LevelFilter;
-    use log4rs::append::file::FileAppender;
-    use log4rs::config::{Appender, Config, Logger, Root};
-    use log4rs::encode::pattern::PatternEncoder;
-
     let write_dir = config.write_dir.clone().unwrap();
-    let mut log_file = PathBuf::from(&write_dir);
-    log_file.push("Logs/dcs-ts.log");
-
-    let requests = FileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(
-            "{d(%Y-%m-%d %H:%M:%S%.3f)} {l:<7} {t}: {m}{n}",
-        )))
-        .append(false)
-        .build(log_file)
-        .unwrap();
-
-    let log_level = if config.debugging {
-        LevelFilter::Debug
-    } else {
-        LevelFilter::Info
-    };
+    logging::init(&write_dir, &config.logging, config.debugging);
+}
 
-    let log_config = Config::builder()
-        .appender(Appender::builder().build("file", Box::new(requests)))
-        .logger(Logger::builder().build("dcs_ts", log_level))
-        .build(Root::builder().appender("file").build(LevelFilter::Off))
-        .unwrap();
+/// Starts the scripts of `config` with their tasks submitted to `backend`, for
+/// hosts embedding the runtime. `config.write_dir` is required.
+///
+/// The runtime is process wide, a process runs one at a time until [`stop`].
+pub fn start(config: Config, backend: Box<dyn DcsBackend + Send>) -> Result<(), AnyError> {
+    if config.write_dir.is_none() {
+        return Err(generic_error("no write dir configured"));
+    }
+    start_runtime(&config, backend)
+}
 
-    log4rs::init_config(log_config).unwrap();
+/// Stops the runtime started last, terminating its scripts. Tasks still waiting
+/// for a result fail with an error telling that the runtime stopped, rather than
+/// the one of tasks discarded by a reload. Returns whether a runtime was running.
+pub fn stop() -> bool {
+    // dropped outside of the lock, workers still running may need it to wind down
+    let runtime = RUNTIME.lock().unwrap().take();
+    match runtime {
+        Some(mut runtime) => {
+            runtime.shutdown();
+            log::info!("runtime stopped");
+            true
+        }
+        None => false,
+    }
+}
+
+fn start_runtime(config: &Config, backend: Box<dyn DcsBackend + Send>) -> Result<(), AnyError> {
+    if RUNTIME.lock().unwrap().is_some() {
+        return Err(generic_error("runtime is already initialized"));
+    }
+    init(config);
+
+    let runtime = Runtime::new(config.clone(), backend);
+    *(RUNTIME.lock().unwrap()) = Some(runtime);
+    log::info!("runtime created");
+
+    if let Some(mission) = &config.mission {
+        log::info!(
+            "initializing for mission {} ({}) on {}",
+            mission.name.as_deref().unwrap_or("<unknown>"),
+            mission.file.as_deref().unwrap_or("<unknown>"),
+            mission.theatre.as_deref().unwrap_or("<unknown>")
+        );
+    }
+
+    RUNTIME.lock().unwrap().as_mut().unwrap().initialize();
+    log::info!("runtime initialized");
+    Ok(())
 }
 
 #[no_mangle]
-pub fn initialize(lua: &Lua, write_dir: String) -> LuaResult<mlua::Value> {
+pub fn initialize<'lua>(
+    lua: &'lua Lua,
+    (write_dir, mission): (String, Option<mlua::Value<'lua>>),
+) -> LuaResult<mlua::Value<'lua>> {
     let mut config_path = PathBuf::from(&write_dir);
     config_path.push("Config/ts.json");
+    // written before loading so it is available to fix an invalid config
+    let schema_path = config_path.with_file_name("ts.schema.json");
+    let schema_result = Config::write_schema(&schema_path);
 
-    let file = File::open(config_path)?;
-    let reader = BufReader::new(file);
-    let mut config: Config = match serde_json::from_reader(reader) {
+    let mut config = match Config::load(&config_path) {
         Ok(config) => config,
         Err(error) => {
             log::error!("failed to load config: {}", error);
@@ -74,19 +120,18 @@ pub fn initialize(lua: &Lua, write_dir: String) -> LuaResult<mlua::Value> {
         }
     };
     config.write_dir = Some(write_dir);
+    config.mission = match mission {
+        Some(mission) => Some(lua.from_value(mission)?),
+        None => None,
+    };
 
-    {
-        if RUNTIME.lock().unwrap().is_some() {
-            return Err("runtime is already initialized".to_lua_err());
-        }
-        init(&config);
-
-        let runtime = Runtime::new(config.clone());
-        *(RUNTIME.lock().unwrap()) = Some(runtime);
-        log::info!("runtime created");
-
-        RUNTIME.lock().unwrap().as_mut().unwrap().initialize();
-        log::info!("runtime initialized");
+    start_runtime(&config, Box::new(QueueBackend::default())).map_err(|e| e.to_lua_err())?;
+    if let Err(error) = schema_result {
+        log::warn!(
+            "failed to write config schema {}: {}",
+            schema_path.display(),
+            error
+        );
     }
 
     let bytes = include_bytes!("bridge.lua");
@@ -99,7 +144,7 @@ pub fn initialize(lua: &Lua, write_dir: String) -> LuaResult<mlua::Value> {
 }
 
 #[no_mangle]
-pub fn get_queued_tasks(lua: &Lua, _: ()) -> LuaResult<mlua::Value> {
+pub fn get_queued_tasks(lua: &Lua, sim_time: Option<f64>) -> LuaResult<mlua::Value> {
     let runtime_lock = RUNTIME.lock();
     return match runtime_lock {
         Err(_) => {
@@ -111,7 +156,7 @@ pub fn get_queued_tasks(lua: &Lua, _: ()) -> LuaResult<mlua::Value> {
                 return Err("invalid runtime".to_lua_err());
             }
 
-            let table = runtime.as_mut().unwrap().get_queued_tasks(lua);
+            let table = runtime.as_mut().unwrap().get_queued_tasks(lua, sim_time);
             if table.is_some() {
                 log::debug!("get_queued_tasks()",);
             }
@@ -120,58 +165,88 @@ pub fn get_queued_tasks(lua: &Lua, _: ()) -> LuaResult<mlua::Value> {
     };
 }
 
+/// Boots the scripts outside of DCS and feeds them the Lua side recorded in the
+/// journal at `journal_path`, for running from a standalone Lua 5.1 interpreter.
+#[no_mangle]
+pub fn replay<'lua>(
+    lua: &'lua Lua,
+    (write_dir, journal_path): (String, String),
+) -> LuaResult<mlua::Value<'lua>> {
+    let mut backend = JournalBackend::open(Path::new(&journal_path)).map_err(|e| e.to_lua_err())?;
+    runner::boot(
+        &write_dir,
+        backend.mission(),
+        Box::new(QueueBackend::default()),
+    )
+    .map_err(|e| e.to_lua_err())?;
+
+    log::info!("replaying {}", journal_path);
+    let report = journal::replay(&mut backend).map_err(|e| e.to_lua_err())?;
+    match &report.divergence {
+        Some(divergence) => log::warn!("replay diverged: {}", divergence),
+        None => log::info!("replayed {} records", report.replayed),
+    }
+    lua.to_value(&report)
+}
+
 #[no_mangle]
 pub fn add_task_results(lua: &Lua, results: mlua::Table) -> LuaResult<()> {
     log::debug!("add_task_results");
-    let mut runtime = RUNTIME.lock().unwrap();
-    if runtime.is_some() {
-        for result in results.sequence_values() {
-            if let Value::Table(table) = result? {
-                let id: u64 = table.get("id")?;
-
-                let maybe_result: LuaResult<TaskResultValue> = lua.from_value(table.get("result")?);
-
-                match maybe_result {
-                    Ok(result) => {
-                        runtime
-                            .as_mut()
-                            .unwrap()
-                            .complete_task(TaskResult { id, result });
-                    }
-                    Err(error) => {
-                        runtime.as_mut().unwrap().complete_task(TaskResult {
-                            id,
-                            result: TaskResultValue::Error(format!(
-                                "failed to process task result: {}",
-                                error
-                            )),
-                        });
-                    }
-                }
-            }
+    for result in results.sequence_values() {
+        if let Value::Table(table) = result? {
+            let id: u64 = table.get("id")?;
+
+            let maybe_result: LuaResult<TaskResultValue> = lua.from_value(table.get("result")?);
+            let result = maybe_result.unwrap_or_else(|error| {
+                TaskResultValue::Error(format!("failed to process task result: {}", error))
+            });
+            backend::complete(TaskResult { id, result }).map_err(|e| e.to_lua_err())?;
         }
-        return Ok(());
     }
-    Err("invalid runtime".to_lua_err())
+    Ok(())
 }
 
 #[no_mangle]
 pub fn lua_channel_send(lua: &Lua, (channel, msg): (mlua::Number, mlua::Table)) -> LuaResult<bool> {
     log::trace!("lua_channel_send (channel = {})", channel);
+    let msg_json: serde_json::Value = lua.from_value(mlua::Value::Table(msg))?;
+    backend::publish(channel.round() as u64, msg_json).map_err(|e| e.to_lua_err())
+}
+
+#[no_mangle]
+pub fn lua_channel_receive(lua: &Lua, channel: mlua::Number) -> LuaResult<mlua::Value> {
     let mut runtime = RUNTIME.lock().unwrap();
-    if runtime.is_some() {
-        let msg_json: serde_json::Value = lua.from_value(mlua::Value::Table(msg))?;
-        return Ok(runtime
-            .as_mut()
-            .unwrap()
-            .send_user_channel_message(channel.round() as u64, msg_json));
+    if let Some(runtime) = runtime.as_mut() {
+        let messages = runtime.receive_user_channel_messages(channel.round() as u64);
+        if messages.is_empty() {
+            return Ok(mlua::Nil);
+        }
+        return lua.to_value(&messages);
     }
     Err("invalid runtime".to_lua_err())
 }
 
+#[no_mangle]
+pub fn get_load_report(lua: &Lua, _: ()) -> LuaResult<mlua::Value> {
+    let runtime = RUNTIME.lock().unwrap();
+    match runtime.as_ref() {
+        Some(runtime) => lua.to_value(&runtime.load_report()),
+        None => Err("invalid runtime".to_lua_err()),
+    }
+}
+
+#[no_mangle]
+pub fn get_log_records(lua: &Lua, _: ()) -> LuaResult<mlua::Value> {
+    let records = logging::take_dcs_log_records();
+    if records.is_empty() {
+        return Ok(mlua::Nil);
+    }
+    lua.to_value(&records)
+}
+
 #[no_mangle]
 pub fn lua_log(_: &Lua, err: String) -> LuaResult<()> {
-    log::info!("[lua] {}", err);
+    log::info!(target: logging::LUA_TARGET, "[lua] {}", err);
     Ok(())
 }
 
@@ -190,7 +265,7 @@ pub enum Error {
     SerializeParams(#[source] mlua::Error),
 }
 
-#[mlua::lua_module]
+#[cfg_attr(feature = "module", mlua::lua_module)]
 pub fn dcs_ts(lua: &Lua) -> LuaResult<LuaTable> {
     log::info!("dcs_ts lua init called!");
     let exports = lua.create_table()?;
@@ -199,5 +274,9 @@ pub fn dcs_ts(lua: &Lua) -> LuaResult<LuaTable> {
     exports.set("get_queued_tasks", lua.create_function(get_queued_tasks)?)?;
     exports.set("add_task_results", lua.create_function(add_task_results)?)?;
     exports.set("channel_send", lua.create_function(lua_channel_send)?)?;
+    exports.set("channel_receive", lua.create_function(lua_channel_receive)?)?;
+    exports.set("get_load_report", lua.create_function(get_load_report)?)?;
+    exports.set("get_log_records", lua.create_function(get_log_records)?)?;
+    exports.set("replay", lua.create_function(replay)?)?;
     Ok(exports)
 }
@@ -0,0 +1,231 @@
+use std::{
+    cell::RefCell,
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    pin::Pin,
+    process,
+    rc::Rc,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use deno_ast::{EmitOptions, MediaType, ParseParams, SourceTextInfo};
+use deno_core::{
+    anyhow::Error,
+    error::{generic_error, AnyError},
+    futures::FutureExt,
+    ModuleLoader, ModuleSource, ModuleSourceFuture, ModuleSpecifier, OpState,
+};
+use import_map::ImportMap;
+
+use crate::{remote::RemoteModules, sdk, source_maps::SourceMapStore};
+
+/// Module loader which transpiles TypeScript at load time and resolves bare
+/// specifiers through an optional import map. Transpiled output is cached on disk
+/// keyed by the source contents, so unchanged files are only compiled once.
+pub struct TsModuleLoader {
+    import_map: Option<ImportMap>,
+    compiler: Compiler,
+    remote: Option<RemoteModules>,
+    source_maps: SourceMapStore,
+}
+
+#[derive(Clone)]
+struct Compiler {
+    cache_dir: Option<PathBuf>,
+}
+
+impl TsModuleLoader {
+    pub fn new(
+        import_map: Option<ImportMap>,
+        cache_dir: Option<PathBuf>,
+        remote: Option<RemoteModules>,
+        source_maps: SourceMapStore,
+    ) -> Self {
+        Self {
+            import_map,
+            compiler: Compiler { cache_dir },
+            remote,
+            source_maps,
+        }
+    }
+
+    /// Loads and parses the import map at `path`.
+    pub fn load_import_map(path: &str) -> Result<ImportMap, Error> {
+        let specifier = deno_core::resolve_path(path)?;
+        let json = fs::read_to_string(path)
+            .map_err(|e| generic_error(format!("failed to read import map {}: {}", path, e)))?;
+        ImportMap::from_json(specifier.as_str(), &json)
+            .map_err(|e| generic_error(format!("invalid import map {}: {}", path, e)))
+    }
+}
+
+impl Compiler {
+    fn cache_path(&self, specifier: &ModuleSpecifier, source: &str) -> Option<PathBuf> {
+        let mut hasher = DefaultHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        specifier.as_str().hash(&mut hasher);
+        source.hash(&mut hasher);
+
+        let mut path = self.cache_dir.clone()?;
+        path.push(format!("{:016x}.js", hasher.finish()));
+        Some(path)
+    }
+
+    fn compile(&self, specifier: &ModuleSpecifier, source: String) -> Result<String, AnyError> {
+        let media_type = MediaType::from(specifier);
+        let should_transpile = matches!(
+            media_type,
+            MediaType::TypeScript | MediaType::Tsx | MediaType::Jsx
+        );
+        if !should_transpile {
+            return Ok(source);
+        }
+
+        let cache_path = self.cache_path(specifier, &source);
+        if let Some(cached) = cache_path
+            .as_ref()
+            .and_then(|it| fs::read_to_string(it).ok())
+        {
+            return Ok(cached);
+        }
+
+        let parsed = deno_ast::parse_module(ParseParams {
+            specifier: specifier.to_string(),
+            source: SourceTextInfo::from_string(source),
+            media_type,
+            capture_tokens: false,
+            scope_analysis: false,
+            maybe_syntax: None,
+        })?;
+        let code = parsed
+            .transpile(&EmitOptions {
+                inline_source_map: true,
+                ..Default::default()
+            })?
+            .text;
+
+        if let Some(cache_path) = cache_path {
+            if let Err(e) = write_atomic(&cache_path, code.as_bytes()) {
+                log::warn!("failed to cache compiled module {}: {}", specifier, e);
+            }
+        }
+        Ok(code)
+    }
+}
+
+// distinguishes the temporary files of concurrent writes within this process
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Writes `data` to a temporary file first so a crash never leaves a truncated
+/// file behind. Every write gets its own temporary file, so workers writing the
+/// same path concurrently never interleave.
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(format!(
+        ".{}-{}.tmp",
+        process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp_path = PathBuf::from(tmp_path);
+    let result = fs::write(&tmp_path, data).and_then(|_| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    Ok(result?)
+}
+
+impl ModuleLoader for TsModuleLoader {
+    fn resolve(
+        &self,
+        _op_state: Rc<RefCell<OpState>>,
+        specifier: &str,
+        referrer: &str,
+        _is_main: bool,
+    ) -> Result<ModuleSpecifier, AnyError> {
+        // the embedded sdk always wins over an import map, so scripts never end up
+        //  running against an sdk that doesn't match the ops of this build
+        if let Some(resolved) = sdk::resolve(specifier) {
+            return Ok(resolved);
+        }
+        if let Some(import_map) = &self.import_map {
+            if let Ok(resolved) = import_map.resolve(specifier, referrer) {
+                return Ok(resolved);
+            }
+        }
+        Ok(deno_core::resolve_import(specifier, referrer)?)
+    }
+
+    fn load(
+        &self,
+        _op_state: Rc<RefCell<OpState>>,
+        module_specifier: &ModuleSpecifier,
+        _maybe_referrer: Option<ModuleSpecifier>,
+        _is_dyn_import: bool,
+    ) -> Pin<Box<ModuleSourceFuture>> {
+        let module_specifier = module_specifier.clone();
+        let result: Result<Option<ModuleSource>, AnyError> = (|| {
+            if module_specifier.scheme() == sdk::SCHEME {
+                let source = sdk::source(&module_specifier).ok_or_else(|| {
+                    generic_error(format!("no such sdk module {}", module_specifier))
+                })?;
+                self.source_maps.insert(module_specifier.as_str(), source);
+                return Ok(Some(ModuleSource {
+                    code: source.to_string(),
+                    module_url_specified: module_specifier.to_string(),
+                    module_url_found: module_specifier.to_string(),
+                }));
+            }
+            if matches!(module_specifier.scheme(), "http" | "https") {
+                return Ok(None);
+            }
+            if module_specifier.scheme() != "file" {
+                return Err(generic_error(format!(
+                    "unsupported module scheme for {}",
+                    module_specifier
+                )));
+            }
+            let path = module_specifier
+                .to_file_path()
+                .map_err(|_| generic_error(format!("invalid module path {}", module_specifier)))?;
+            let source = fs::read_to_string(&path)
+                .map_err(|e| generic_error(format!("failed to read {}: {}", path.display(), e)))?;
+            let code = self.compiler.compile(&module_specifier, source)?;
+            self.source_maps.insert(module_specifier.as_str(), &code);
+            Ok(Some(ModuleSource {
+                code,
+                module_url_specified: module_specifier.to_string(),
+                module_url_found: module_specifier.to_string(),
+            }))
+        })();
+
+        let remote = self.remote.clone();
+        let compiler = self.compiler.clone();
+        let source_maps = self.source_maps.clone();
+        async move {
+            if let Some(source) = result? {
+                return Ok(source);
+            }
+
+            let remote = remote.ok_or_else(|| {
+                generic_error(format!(
+                    "remote modules are disabled ({})",
+                    module_specifier
+                ))
+            })?;
+            let (final_specifier, source) = remote.load(&module_specifier).await?;
+            let code = compiler.compile(&final_specifier, source)?;
+            source_maps.insert(final_specifier.as_str(), &code);
+            Ok(ModuleSource {
+                code,
+                module_url_specified: module_specifier.to_string(),
+                module_url_found: final_specifier.to_string(),
+            })
+        }
+        .boxed_local()
+    }
+}
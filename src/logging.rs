@@ -0,0 +1,372 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use deno_core::anyhow;
+use log::{Level, LevelFilter, Record};
+use log4rs::{
+    append::{
+        rolling_file::{
+            policy::compound::{
+                roll::fixed_window::FixedWindowRoller,
+                trigger::{size::SizeTrigger, Trigger},
+                CompoundPolicy,
+            },
+            RollingFileAppender,
+        },
+        Append,
+    },
+    config::{Appender, Logger, Root},
+    encode::{json::JsonEncoder, pattern::PatternEncoder, Encode},
+    filter::threshold::ThresholdFilter,
+};
+use once_cell::sync::Lazy;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// records waiting for the bridge beyond this are dropped, oldest first
+const DCS_LOG_CAPACITY: usize = 1000;
+
+static DCS_LOG: Lazy<Mutex<DcsLogQueue>> = Lazy::new(|| Mutex::new(DcsLogQueue::new(0)));
+
+/// Log target of script output (`console.*` and prints from Deno).
+pub const DENO_TARGET: &str = "dcs_ts::deno";
+/// Log target of messages sent from the Lua bridge.
+pub const LUA_TARGET: &str = "dcs_ts::lua";
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => LevelFilter::Off,
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One human readable line per record.
+    Text,
+    /// One JSON object per line, for log shippers.
+    Json,
+}
+
+/// Levels per log target, anything not given follows `debugging`.
+#[derive(Debug, Clone, Deserialize, Serialize, Default, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogLevels {
+    /// Runtime internals.
+    pub dcs_ts: Option<LogLevel>,
+    /// Script output, logged with a `[deno]` prefix.
+    pub deno: Option<LogLevel>,
+    /// Messages from the Lua bridge, logged with a `[lua]` prefix.
+    pub lua: Option<LogLevel>,
+}
+
+/// When log files are rolled over. Rolled files are kept as `<name>.1.log`,
+/// `<name>.2.log` and so on, up to `keep` files.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "trigger", rename_all = "lowercase", deny_unknown_fields)]
+pub enum RollingConfig {
+    /// Roll once the file exceeds `max_size_mb`.
+    Size { max_size_mb: u64, keep: u32 },
+}
+
+impl Default for RollingConfig {
+    fn default() -> Self {
+        RollingConfig::Size {
+            max_size_mb: 10,
+            keep: 5,
+        }
+    }
+}
+
+impl RollingConfig {
+    fn keep(&self) -> u32 {
+        match self {
+            RollingConfig::Size { keep, .. } => *keep,
+        }
+    }
+
+    fn trigger(&self) -> Box<dyn Trigger> {
+        match self {
+            RollingConfig::Size { max_size_mb, .. } => {
+                Box::new(SizeTrigger::new(max_size_mb * 1024 * 1024))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub levels: LogLevels,
+    pub format: LogFormat,
+    pub rolling: RollingConfig,
+    /// Writes script output to this file in `Logs/` instead of `dcs-ts.log`.
+    pub scripts_file: Option<String>,
+    pub dcs_log: DcsLogConfig,
+}
+
+/// Mirrors log records into `dcs.log` through the Lua bridge.
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct DcsLogConfig {
+    /// Off by default, `dcs.log` is shared with DCS itself and every other mod.
+    pub enabled: bool,
+    /// Minimum level of records to mirror.
+    pub level: LogLevel,
+    /// Records written to `dcs.log` per second at most, the rest waits for the
+    /// next poll.
+    pub max_per_second: u32,
+}
+
+impl Default for DcsLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            level: LogLevel::Warn,
+            max_per_second: 20,
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            levels: LogLevels::default(),
+            format: LogFormat::Text,
+            rolling: RollingConfig::default(),
+            scripts_file: None,
+            dcs_log: DcsLogConfig::default(),
+        }
+    }
+}
+
+impl LoggingConfig {
+    fn encoder(&self) -> Box<dyn Encode> {
+        match self.format {
+            LogFormat::Text => Box::new(PatternEncoder::new(
+                "{d(%Y-%m-%d %H:%M:%S%.3f)} {l:<7} {t}: {m}{n}",
+            )),
+            LogFormat::Json => Box::new(JsonEncoder::new()),
+        }
+    }
+
+    fn appender(&self, logs_dir: &Path, file_name: &str) -> Box<dyn Append> {
+        let path = logs_dir.join(file_name);
+        let stem = path
+            .file_stem()
+            .map(|it| it.to_string_lossy().to_string())
+            .unwrap_or_else(|| file_name.to_string());
+        let pattern = logs_dir.join(format!("{}.{{}}.log", stem));
+        let roller = FixedWindowRoller::builder()
+            .build(&pattern.to_string_lossy(), self.rolling.keep())
+            .unwrap();
+        let policy = CompoundPolicy::new(self.rolling.trigger(), Box::new(roller));
+
+        Box::new(
+            RollingFileAppender::builder()
+                .encoder(self.encoder())
+                .build(path, Box::new(policy))
+                .unwrap(),
+        )
+    }
+}
+
+/// A log record waiting to be written to `dcs.log` by the bridge.
+#[derive(Debug, Clone, Serialize)]
+pub struct DcsLogRecord {
+    /// One of `info`, `warning` or `error`, matching the `env.*` function used.
+    pub level: &'static str,
+    pub message: String,
+}
+
+struct DcsLogQueue {
+    records: VecDeque<DcsLogRecord>,
+    dropped: usize,
+    max_per_second: u32,
+    window_start: Instant,
+    sent_in_window: u32,
+}
+
+impl DcsLogQueue {
+    fn new(max_per_second: u32) -> Self {
+        Self {
+            records: VecDeque::new(),
+            dropped: 0,
+            max_per_second,
+            window_start: Instant::now(),
+            sent_in_window: 0,
+        }
+    }
+
+    fn push(&mut self, record: DcsLogRecord) {
+        if self.records.len() >= DCS_LOG_CAPACITY {
+            self.records.pop_front();
+            self.dropped += 1;
+        }
+        self.records.push_back(record);
+    }
+
+    fn take(&mut self) -> Vec<DcsLogRecord> {
+        if self.window_start.elapsed() >= Duration::from_secs(1) {
+            self.window_start = Instant::now();
+            self.sent_in_window = 0;
+        }
+
+        let mut batch = vec![];
+        if self.dropped > 0 && self.sent_in_window < self.max_per_second {
+            batch.push(DcsLogRecord {
+                level: "warning",
+                message: format!("dcs_ts: dropped {} log records", self.dropped),
+            });
+            self.dropped = 0;
+            self.sent_in_window += 1;
+        }
+        while self.sent_in_window < self.max_per_second {
+            match self.records.pop_front() {
+                Some(record) => batch.push(record),
+                None => break,
+            }
+            self.sent_in_window += 1;
+        }
+        batch
+    }
+}
+
+/// Queues log records for the bridge to write into `dcs.log`.
+#[derive(Debug)]
+struct DcsLogAppender;
+
+impl Append for DcsLogAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        let level = match record.level() {
+            Level::Error => "error",
+            Level::Warn => "warning",
+            _ => "info",
+        };
+        DCS_LOG.lock().unwrap().push(DcsLogRecord {
+            level,
+            message: format!("{}: {}", record.target(), record.args()),
+        });
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+/// Takes the next batch of records to write into `dcs.log`, limited to
+/// `max_per_second` records per second.
+pub fn take_dcs_log_records() -> Vec<DcsLogRecord> {
+    DCS_LOG.lock().unwrap().take()
+}
+
+/// Sets up the global logger writing into `Logs/` of the write dir.
+pub fn init(write_dir: &str, config: &LoggingConfig, debugging: bool) {
+    let mut logs_dir = PathBuf::from(write_dir);
+    logs_dir.push("Logs");
+
+    let default_level = if debugging {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    };
+    let level = |level: Option<LogLevel>| level.map(Into::into).unwrap_or(default_level);
+
+    let mut builder = log4rs::Config::builder()
+        .appender(Appender::builder().build("file", config.appender(&logs_dir, "dcs-ts.log")));
+    let mut root = Root::builder().appender("file");
+    let mut deno_logger = Logger::builder();
+    if config.dcs_log.enabled {
+        *DCS_LOG.lock().unwrap() = DcsLogQueue::new(config.dcs_log.max_per_second);
+        builder = builder.appender(
+            Appender::builder()
+                .filter(Box::new(ThresholdFilter::new(config.dcs_log.level.into())))
+                .build("dcs", Box::new(DcsLogAppender)),
+        );
+        root = root.appender("dcs");
+    }
+    if let Some(scripts_file) = &config.scripts_file {
+        builder = builder.appender(
+            Appender::builder().build("scripts", config.appender(&logs_dir, scripts_file)),
+        );
+        deno_logger = deno_logger.appender("scripts").additive(false);
+        if config.dcs_log.enabled {
+            deno_logger = deno_logger.appender("dcs");
+        }
+    }
+
+    let log_config = builder
+        .logger(Logger::builder().build("dcs_ts", level(config.levels.dcs_ts)))
+        .logger(deno_logger.build(DENO_TARGET, level(config.levels.deno)))
+        .logger(Logger::builder().build(LUA_TARGET, level(config.levels.lua)))
+        .build(root.build(LevelFilter::Off))
+        .unwrap();
+
+    log4rs::init_config(log_config).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(message: &str) -> DcsLogRecord {
+        DcsLogRecord {
+            level: "info",
+            message: message.to_string(),
+        }
+    }
+
+    fn messages(records: Vec<DcsLogRecord>) -> Vec<String> {
+        records.into_iter().map(|record| record.message).collect()
+    }
+
+    #[test]
+    fn dcs_log_is_rate_limited_per_second() {
+        let mut queue = DcsLogQueue::new(2);
+        for message in ["a", "b", "c", "d", "e"] {
+            queue.push(record(message));
+        }
+        assert_eq!(messages(queue.take()), vec!["a", "b"]);
+        // the rest waits for the next window
+        assert!(queue.take().is_empty());
+
+        queue.window_start -= Duration::from_secs(1);
+        assert_eq!(messages(queue.take()), vec!["c", "d"]);
+        queue.window_start -= Duration::from_secs(1);
+        assert_eq!(messages(queue.take()), vec!["e"]);
+    }
+
+    #[test]
+    fn dcs_log_reports_dropped_records_first() {
+        let mut queue = DcsLogQueue::new(u32::MAX);
+        for index in 0..DCS_LOG_CAPACITY + 3 {
+            queue.push(record(&index.to_string()));
+        }
+        let records = messages(queue.take());
+        assert_eq!(records.len(), DCS_LOG_CAPACITY + 1);
+        assert_eq!(records[0], "dcs_ts: dropped 3 log records");
+        assert_eq!(records[1], "3");
+        assert!(queue.take().is_empty());
+    }
+}
@@ -0,0 +1,47 @@
+// Reports permission denials to the runtime as they happen, along with the stack
+// of the call that hit them so that the script responsible can be named.
+"use strict";
+
+((window) => {
+  const core = window.Deno.core;
+  const { opSync, opAsync } = core;
+
+  function report(error, trace) {
+    if (error?.name !== "PermissionDenied") {
+      return;
+    }
+    try {
+      opSync("op_dcs_permission_denied", {
+        message: error.message,
+        stack: trace.stack,
+      });
+    } catch {
+      // reporting must never hide the denial itself
+    }
+  }
+
+  core.opSync = function (...args) {
+    try {
+      return opSync(...args);
+    } catch (error) {
+      report(error, new Error());
+      throw error;
+    }
+  };
+
+  core.opAsync = function (...args) {
+    // captured up front since the caller is gone once the op settles, the stack
+    //  is only formatted for denials
+    const trace = new Error();
+    const promise = opAsync(...args);
+    const reported = promise.catch((error) => {
+      report(error, trace);
+      throw error;
+    });
+    // keeps what deno attaches to the promise of an op, like its id
+    for (const key of Object.getOwnPropertySymbols(promise)) {
+      reported[key] = promise[key];
+    }
+    return reported;
+  };
+})(globalThis);
@@ -0,0 +1,294 @@
+use std::path::PathBuf;
+
+use deno_runtime::permissions::{Permissions, PermissionsOptions};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A single permission grant, either `true` to allow everything of that kind or
+/// an allow-list of entries (paths, hosts, variable names or binaries).
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[serde(untagged)]
+pub enum PermissionGrant {
+    All(bool),
+    List(Vec<String>),
+}
+
+impl PermissionGrant {
+    fn merge(a: &Option<PermissionGrant>, b: &Option<PermissionGrant>) -> Option<PermissionGrant> {
+        match (a, b) {
+            (_, Some(PermissionGrant::All(false))) => None,
+            (Some(PermissionGrant::All(true)), _) | (_, Some(PermissionGrant::All(true))) => {
+                Some(PermissionGrant::All(true))
+            }
+            (Some(PermissionGrant::List(a)), Some(PermissionGrant::List(b))) => {
+                let mut merged = a.clone();
+                merged.extend(b.iter().filter(|it| !a.contains(it)).cloned());
+                Some(PermissionGrant::List(merged))
+            }
+            (Some(PermissionGrant::List(list)), _) | (_, Some(PermissionGrant::List(list))) => {
+                Some(PermissionGrant::List(list.clone()))
+            }
+            _ => None,
+        }
+    }
+
+    // deno treats an empty allow-list as "allow everything", so empty lists and
+    //  `false` are both mapped to a denial
+    fn to_allowlist(grant: &Option<PermissionGrant>) -> Option<Vec<String>> {
+        match grant {
+            Some(PermissionGrant::All(true)) => Some(vec![]),
+            Some(PermissionGrant::List(list)) if !list.is_empty() => Some(list.clone()),
+            _ => None,
+        }
+    }
+
+    fn to_path_allowlist(grant: &Option<PermissionGrant>) -> Option<Vec<PathBuf>> {
+        PermissionGrant::to_allowlist(grant)
+            .map(|list| list.into_iter().map(PathBuf::from).collect())
+    }
+}
+
+/// Permissions granted to scripts, mapping onto deno's `--allow-*` flags. Anything
+/// not granted is denied.
+#[derive(Debug, Clone, Deserialize, Serialize, Default, PartialEq, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct PermissionsConfig {
+    pub all: bool,
+    pub read: Option<PermissionGrant>,
+    pub write: Option<PermissionGrant>,
+    pub net: Option<PermissionGrant>,
+    pub env: Option<PermissionGrant>,
+    pub run: Option<PermissionGrant>,
+    pub ffi: Option<PermissionGrant>,
+    pub hrtime: bool,
+}
+
+impl PermissionsConfig {
+    /// Combines the global permissions with those of a script. The script's grants
+    /// add to the global ones, except for a kind set to `false` which the script is
+    /// denied even if it is granted globally.
+    pub fn merge(&self, other: &PermissionsConfig) -> PermissionsConfig {
+        // a global `all` is spelled out per kind, so that the script can still be
+        //  denied some of them
+        let global = if self.all && !other.all && other.denies_any() {
+            PermissionsConfig {
+                all: false,
+                read: Some(PermissionGrant::All(true)),
+                write: Some(PermissionGrant::All(true)),
+                net: Some(PermissionGrant::All(true)),
+                env: Some(PermissionGrant::All(true)),
+                run: Some(PermissionGrant::All(true)),
+                ffi: Some(PermissionGrant::All(true)),
+                hrtime: true,
+            }
+        } else {
+            self.clone()
+        };
+        PermissionsConfig {
+            all: global.all || other.all,
+            read: PermissionGrant::merge(&global.read, &other.read),
+            write: PermissionGrant::merge(&global.write, &other.write),
+            net: PermissionGrant::merge(&global.net, &other.net),
+            env: PermissionGrant::merge(&global.env, &other.env),
+            run: PermissionGrant::merge(&global.run, &other.run),
+            ffi: PermissionGrant::merge(&global.ffi, &other.ffi),
+            hrtime: global.hrtime || other.hrtime,
+        }
+    }
+
+    fn denies_any(&self) -> bool {
+        [
+            &self.read,
+            &self.write,
+            &self.net,
+            &self.env,
+            &self.run,
+            &self.ffi,
+        ]
+        .iter()
+        .any(|grant| matches!(grant, Some(PermissionGrant::All(false))))
+    }
+
+    pub fn to_permissions(&self) -> Permissions {
+        if self.all {
+            return Permissions::allow_all();
+        }
+
+        Permissions::from_options(&PermissionsOptions {
+            allow_env: PermissionGrant::to_allowlist(&self.env),
+            allow_hrtime: self.hrtime,
+            allow_net: PermissionGrant::to_allowlist(&self.net),
+            allow_ffi: PermissionGrant::to_path_allowlist(&self.ffi),
+            allow_read: PermissionGrant::to_path_allowlist(&self.read),
+            allow_run: PermissionGrant::to_allowlist(&self.run),
+            allow_write: PermissionGrant::to_path_allowlist(&self.write),
+            prompt: false,
+        })
+    }
+}
+
+/// Wraps the ops of every worker so that permission denials are reported through
+/// `op_dcs_permission_denied` where they happen, caught or not.
+pub const DENIAL_HOOK: &str = include_str!("permissions.js");
+
+/// A permission denial reported by [`DENIAL_HOOK`], with the stack of the call
+/// that hit it.
+#[derive(Debug, Deserialize)]
+pub struct PermissionDenial {
+    pub message: String,
+    pub stack: Option<String>,
+}
+
+/// Logs a denial on `worker` along with the script that hit it. `scripts` are the
+/// module urls of the worker's scripts.
+pub fn log_denial(worker: &str, denial: &PermissionDenial, scripts: &[String]) {
+    let script = denial
+        .stack
+        .as_deref()
+        .and_then(|stack| denied_by(stack, scripts))
+        .unwrap_or_else(|| format!("[{}]", scripts.join(", ")));
+    log::error!(
+        "permission denied for script {} on worker {}: {} (grant it in the `permissions` block of ts.json)",
+        script,
+        worker,
+        denial.message
+    );
+}
+
+/// Names the script a denial comes from: the innermost frame of one of the worker's
+/// scripts, or else the innermost frame of a module outside the runtime, such as
+/// the main module of a web worker.
+fn denied_by(stack: &str, scripts: &[String]) -> Option<String> {
+    let urls: Vec<&str> = frame_urls(stack).collect();
+    urls.iter()
+        .find(|url| scripts.iter().any(|script| script == *url))
+        .or_else(|| {
+            urls.iter().find(|url| {
+                !url.starts_with("deno:") && !url.starts_with("dcs:") && url.contains(':')
+            })
+        })
+        .map(|url| url.to_string())
+}
+
+// frames look like `    at name (url:line:column)` or `    at [async ]url:line:column`
+fn frame_urls(stack: &str) -> impl Iterator<Item = &str> {
+    stack.lines().filter_map(|line| {
+        let location = line.trim().strip_prefix("at ")?;
+        let location = location.strip_prefix("async ").unwrap_or(location);
+        let location = match location.rfind('(') {
+            Some(start) => location[start + 1..].trim_end_matches(')'),
+            None => location,
+        };
+        let mut parts = location.rsplitn(3, ':');
+        let (_column, _line) = (parts.next()?, parts.next()?);
+        parts.next()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grant(list: &[&str]) -> Option<PermissionGrant> {
+        Some(PermissionGrant::List(
+            list.iter().map(|it| it.to_string()).collect(),
+        ))
+    }
+
+    #[test]
+    fn script_grants_add_to_the_global_ones() {
+        let global = PermissionsConfig {
+            net: grant(&["a.com", "b.com"]),
+            read: Some(PermissionGrant::All(true)),
+            ..Default::default()
+        };
+        let script = PermissionsConfig {
+            net: grant(&["b.com", "c.com"]),
+            read: grant(&["/data"]),
+            env: grant(&["HOME"]),
+            hrtime: true,
+            ..Default::default()
+        };
+        let merged = global.merge(&script);
+        assert_eq!(merged.net, grant(&["a.com", "b.com", "c.com"]));
+        assert_eq!(merged.read, Some(PermissionGrant::All(true)));
+        assert_eq!(merged.env, grant(&["HOME"]));
+        assert_eq!(merged.write, None);
+        assert!(merged.hrtime);
+        assert!(!merged.all);
+    }
+
+    #[test]
+    fn script_grants_of_false_take_global_ones_away() {
+        let global = PermissionsConfig {
+            net: grant(&["a.com"]),
+            read: Some(PermissionGrant::All(true)),
+            ..Default::default()
+        };
+        let script = PermissionsConfig {
+            net: Some(PermissionGrant::All(false)),
+            ..Default::default()
+        };
+        let merged = global.merge(&script);
+        assert_eq!(merged.net, None);
+        assert_eq!(merged.read, Some(PermissionGrant::All(true)));
+
+        // a global `all` is kept for everything the script is not denied
+        let global = PermissionsConfig {
+            all: true,
+            ..Default::default()
+        };
+        let merged = global.merge(&script);
+        assert!(!merged.all);
+        assert_eq!(merged.net, None);
+        assert_eq!(merged.write, Some(PermissionGrant::All(true)));
+        assert!(merged.hrtime);
+        // without a denial the global `all` stays as it is
+        assert_eq!(global.merge(&PermissionsConfig::default()), global);
+    }
+
+    #[test]
+    fn grants_map_onto_deno_allowlists() {
+        assert_eq!(
+            PermissionGrant::to_allowlist(&Some(PermissionGrant::All(true))),
+            Some(vec![])
+        );
+        assert_eq!(
+            PermissionGrant::to_allowlist(&grant(&["a.com"])),
+            Some(vec!["a.com".to_string()])
+        );
+        // deno would read an empty list as allowing everything
+        assert_eq!(PermissionGrant::to_allowlist(&grant(&[])), None);
+        assert_eq!(
+            PermissionGrant::to_allowlist(&Some(PermissionGrant::All(false))),
+            None
+        );
+        assert_eq!(PermissionGrant::to_allowlist(&None), None);
+        assert_eq!(
+            PermissionGrant::to_path_allowlist(&grant(&["/data"])),
+            Some(vec![PathBuf::from("/data")])
+        );
+    }
+
+    #[test]
+    fn denials_name_the_script_from_the_stack() {
+        let scripts = vec!["file:///scripts/http.js".to_string()];
+        let stack = "Error\n    at Object.core.opAsync (dcs:ext/permissions.js:34:19)\n    \
+                     at opFetch (deno:ext/fetch/26_fetch.js:48:17)\n    \
+                     at fetchData (file:///scripts/lib.js:3:9)\n    \
+                     at async file:///scripts/http.js:10:5";
+        assert_eq!(
+            denied_by(stack, &scripts).as_deref(),
+            Some("file:///scripts/http.js")
+        );
+        // modules of web workers are not configured scripts
+        assert_eq!(
+            denied_by(stack, &[]).as_deref(),
+            Some("file:///scripts/lib.js")
+        );
+        assert_eq!(
+            denied_by("Error\n    at deno:ext/fetch/26_fetch.js:48:17", &scripts),
+            None
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use std::{collections::BTreeMap, fs, path::PathBuf, sync::Mutex};
+
+use deno_core::{anyhow::Error, error::generic_error, ModuleSpecifier};
+use deno_runtime::deno_fetch::reqwest;
+use once_cell::sync::Lazy;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::loader::write_atomic;
+
+// workers on different threads share the lockfile, updates are serialized here
+static LOCKFILE_MUTEX: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+#[derive(Debug, Clone, Deserialize, Serialize, Default, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModulesConfig {
+    /// Never fetch remote modules, only serve them from the on-disk cache.
+    pub offline: bool,
+    /// Path of the lockfile storing integrity hashes of remote modules, defaults to
+    /// `Config/ts-lock.json` in the write dir.
+    pub lockfile: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct CachedModule {
+    specifier: String,
+    final_specifier: String,
+    source: String,
+}
+
+/// Fetches `http:` and `https:` modules, keeping a persistent copy of each on
+/// disk and verifying them against a lockfile of integrity hashes.
+#[derive(Clone)]
+pub struct RemoteModules {
+    cache_dir: PathBuf,
+    lockfile: PathBuf,
+    offline: bool,
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl RemoteModules {
+    pub fn new(write_dir: &str, config: &ModulesConfig) -> Self {
+        let mut cache_dir = PathBuf::from(write_dir);
+        cache_dir.push("Cache/ts/remote");
+        let lockfile = match &config.lockfile {
+            Some(lockfile) => PathBuf::from(lockfile),
+            None => {
+                let mut lockfile = PathBuf::from(write_dir);
+                lockfile.push("Config/ts-lock.json");
+                lockfile
+            }
+        };
+        Self {
+            cache_dir,
+            lockfile,
+            offline: config.offline,
+        }
+    }
+
+    fn cache_path(&self, specifier: &ModuleSpecifier) -> PathBuf {
+        let mut path = self.cache_dir.clone();
+        path.push(format!(
+            "{}.json",
+            sha256_hex(specifier.as_str().as_bytes())
+        ));
+        path
+    }
+
+    fn read_lockfile(&self) -> Result<BTreeMap<String, String>, Error> {
+        match fs::read_to_string(&self.lockfile) {
+            Ok(contents) => Ok(serde_json::from_str(&contents).map_err(|e| {
+                generic_error(format!(
+                    "invalid lockfile {}: {}",
+                    self.lockfile.display(),
+                    e
+                ))
+            })?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Checks `source` against the hash recorded for `specifier`, recording it if
+    /// the module has never been seen before.
+    fn check_integrity(&self, specifier: &ModuleSpecifier, source: &str) -> Result<(), Error> {
+        let _guard = LOCKFILE_MUTEX.lock().unwrap();
+        let mut lock = self.read_lockfile()?;
+        let hash = sha256_hex(source.as_bytes());
+        match lock.get(specifier.as_str()) {
+            Some(expected) if *expected == hash => Ok(()),
+            Some(expected) => Err(generic_error(format!(
+                "integrity check failed for {}: expected {}, got {} (remove it from {} if the change is expected)",
+                specifier,
+                expected,
+                hash,
+                self.lockfile.display()
+            ))),
+            None if self.offline => Err(generic_error(format!(
+                "{} is not in the lockfile and remote modules are offline",
+                specifier
+            ))),
+            None => {
+                lock.insert(specifier.to_string(), hash);
+                write_atomic(&self.lockfile, &serde_json::to_vec_pretty(&lock)?)
+            }
+        }
+    }
+
+    fn read_cache(&self, specifier: &ModuleSpecifier) -> Option<CachedModule> {
+        let contents = fs::read(self.cache_path(specifier)).ok()?;
+        serde_json::from_slice(&contents).ok()
+    }
+
+    /// Returns the final url (after redirects) and source of the module at
+    /// `specifier`, served from the cache when possible.
+    pub async fn load(
+        &self,
+        specifier: &ModuleSpecifier,
+    ) -> Result<(ModuleSpecifier, String), Error> {
+        if let Some(cached) = self.read_cache(specifier) {
+            self.check_integrity(specifier, &cached.source)?;
+            return Ok((
+                ModuleSpecifier::parse(&cached.final_specifier)?,
+                cached.source,
+            ));
+        }
+
+        if self.offline {
+            return Err(generic_error(format!(
+                "{} is not cached and remote modules are offline",
+                specifier
+            )));
+        }
+
+        log::info!("downloading {}", specifier);
+        let response = reqwest::get(specifier.clone()).await?;
+        if !response.status().is_success() {
+            return Err(generic_error(format!(
+                "failed to download {}: {}",
+                specifier,
+                response.status()
+            )));
+        }
+        let final_specifier = response.url().clone();
+        let source = response.text().await?;
+        self.check_integrity(specifier, &source)?;
+
+        let cached = CachedModule {
+            specifier: specifier.to_string(),
+            final_specifier: final_specifier.to_string(),
+            source,
+        };
+        if let Err(e) = write_atomic(&self.cache_path(specifier), &serde_json::to_vec(&cached)?) {
+            log::warn!("failed to cache remote module {}: {}", specifier, e);
+        }
+        Ok((final_specifier, cached.source))
+    }
+}
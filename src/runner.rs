@@ -0,0 +1,323 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use deno_core::{anyhow::Error, error::generic_error};
+use serde::Deserialize;
+
+pub use crate::journal::JournalBackend;
+pub use crate::runtime::{Task, TaskResultValue};
+use crate::{
+    backend::{complete, poll, publish, DcsBackend, Reply},
+    config::{Config, MissionInfo},
+    runtime::TaskResult,
+};
+
+// the rate the bridge polls at within DCS
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+#[derive(Debug, Default)]
+pub struct RunReport {
+    pub tasks: u64,
+    pub messages: u64,
+    /// Whether the run ended because the backend finished, rather than the
+    /// scripts going idle.
+    pub finished: bool,
+}
+
+/// Loads `Config/ts.json` of `write_dir` and starts the scripts for `mission`,
+/// like the bridge does when a mission starts within DCS, but without recording
+/// a journal. Tasks of the scripts are submitted to `backend`, [`run`] needs one
+/// keeping them for polling like [`QueueBackend`](crate::backend::QueueBackend).
+///
+/// This is how `dcs-ts-run` and replays boot, hosts with a config of their own
+/// use [`start`](crate::start).
+pub fn boot(
+    write_dir: &str,
+    mission: Option<MissionInfo>,
+    backend: Box<dyn DcsBackend + Send>,
+) -> Result<(), Error> {
+    let mut config = Config::load(&PathBuf::from(write_dir).join("Config/ts.json"))?;
+    config.write_dir = Some(write_dir.to_string());
+    // only missions within DCS are recorded, replays must not record over the
+    //  journal they replay
+    config.journal.enabled = false;
+    config.mission = mission;
+    crate::start(config, backend)
+}
+
+/// Polls the scripts for tasks like the bridge does, submitting them to `backend`
+/// and handing its replies back. Runs until the backend finished, or the scripts neither queued a
+/// task nor were sent a message for `idle_timeout`. Scripts can also end the
+/// process with `Deno.exit`.
+pub fn run(
+    backend: &mut dyn DcsBackend,
+    idle_timeout: Option<Duration>,
+) -> Result<RunReport, Error> {
+    let mut report = RunReport::default();
+    let mut last_activity = Instant::now();
+    loop {
+        // the backend runs without holding the runtime, so scripts can keep
+        //  queueing tasks meanwhile
+        let tasks = poll()?;
+        report.tasks += tasks.len() as u64;
+        let queued = !tasks.is_empty();
+        for task in tasks {
+            backend.submit(task);
+        }
+        let replies = backend.take_replies();
+
+        if queued || !replies.is_empty() {
+            last_activity = Instant::now();
+        }
+        for reply in replies {
+            match reply {
+                Reply::Result(result) => complete(result)?,
+                Reply::Message { channel, message } => {
+                    report.messages += 1;
+                    if !publish(channel, message)? {
+                        log::debug!("dropped message for channel {}", channel);
+                    }
+                }
+            }
+        }
+
+        if backend.finished() {
+            report.finished = true;
+            break;
+        }
+        if idle_timeout.map_or(false, |timeout| last_activity.elapsed() > timeout) {
+            break;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(report)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Fixture {
+    Result(TaskResultValue),
+    /// Results returned in turn, the last one is repeated.
+    Sequence(Vec<TaskResultValue>),
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Fixtures {
+    tasks: HashMap<String, Fixture>,
+    /// Mission events sent to every event producer the scripts create.
+    events: Vec<serde_json::Value>,
+}
+
+/// Answers tasks with canned results from a JSON file, keyed by task target:
+///
+/// ```json
+/// {
+///   "tasks": {
+///     "getTime": { "type": "Ok", "value": 3600 },
+///     "unitGetByName": [{ "type": "Ok", "value": null }, { "type": "Error", "value": "gone" }]
+///   },
+///   "events": [{ "id": 15, "time": 3600 }]
+/// }
+/// ```
+///
+/// Tasks without a fixture fail.
+#[derive(Default)]
+pub struct FixtureBackend {
+    fixtures: Fixtures,
+    // number of results returned per target
+    calls: HashMap<String, usize>,
+    outbox: Vec<Reply>,
+}
+
+impl FixtureBackend {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let fixtures = serde_json::from_slice(&fs::read(path)?)
+            .map_err(|e| generic_error(format!("invalid fixtures {}: {}", path.display(), e)))?;
+        Ok(Self {
+            fixtures,
+            ..Default::default()
+        })
+    }
+}
+
+impl FixtureBackend {
+    fn run_task(&mut self, task: &Task) -> TaskResultValue {
+        if task.target == "createEventProducer" {
+            if let Some(channel) = task
+                .args
+                .as_ref()
+                .and_then(|args| args.pointer("/channel/id"))
+                .and_then(|id| id.as_u64())
+            {
+                for event in &self.fixtures.events {
+                    self.outbox.push(Reply::Message {
+                        channel,
+                        message: event.clone(),
+                    });
+                }
+                return TaskResultValue::Ok(None);
+            }
+        }
+
+        let calls = self.calls.entry(task.target.clone()).or_default();
+        let result = match self.fixtures.tasks.get(&task.target) {
+            Some(Fixture::Result(result)) => result.clone(),
+            Some(Fixture::Sequence(results)) if !results.is_empty() => {
+                results[(*calls).min(results.len() - 1)].clone()
+            }
+            _ => {
+                log::warn!("no fixture for task {}", task.target);
+                TaskResultValue::Error(format!("no fixture for task {}", task.target))
+            }
+        };
+        *calls += 1;
+        result
+    }
+}
+
+impl DcsBackend for FixtureBackend {
+    fn submit(&mut self, task: Task) {
+        // events of an event producer follow the result creating it
+        let index = self.outbox.len();
+        let result = self.run_task(&task);
+        self.outbox.insert(
+            index,
+            Reply::Result(TaskResult {
+                id: task.id,
+                result,
+            }),
+        );
+    }
+
+    fn take_replies(&mut self) -> Vec<Reply> {
+        std::mem::take(&mut self.outbox)
+    }
+}
+
+#[cfg(feature = "harness")]
+pub use lua::LuaBackend;
+
+#[cfg(feature = "harness")]
+mod lua {
+    use std::{cell::RefCell, fs, path::Path, rc::Rc};
+
+    use deno_core::{anyhow::Error, error::generic_error};
+    use mlua::prelude::*;
+
+    use super::{DcsBackend, Reply, Task, TaskResult, TaskResultValue};
+
+    /// Answers tasks with a Lua script standing in for the mission, which returns
+    /// its task handlers keyed by target and optionally a function polled along
+    /// with the tasks:
+    ///
+    /// ```lua
+    /// local events
+    /// return {
+    ///   tasks = {
+    ///     getTime = function(args) return 3600 end,
+    ///     createEventProducer = function(args) events = args.channel.id end,
+    ///   },
+    ///   poll = function()
+    ///     if events ~= nil then send(events, { id = 15 }) end
+    ///   end,
+    /// }
+    /// ```
+    ///
+    /// `send(channel, message)` sends a message to the scripts, errors raised by a
+    /// handler fail its task.
+    pub struct LuaBackend {
+        lua: Lua,
+        tasks: LuaRegistryKey,
+        poll: Option<LuaRegistryKey>,
+        outbox: Rc<RefCell<Vec<Reply>>>,
+    }
+
+    impl LuaBackend {
+        pub fn open(path: &Path) -> Result<Self, Error> {
+            let source = fs::read(path)?;
+            let lua = Lua::new();
+            let outbox = Rc::new(RefCell::new(vec![]));
+            let to_lua_error =
+                |e: LuaError| generic_error(format!("failed to load {}: {}", path.display(), e));
+
+            let (tasks, poll) = {
+                let send_outbox = outbox.clone();
+                let send = lua
+                    .create_function(move |lua, (channel, message): (u64, LuaValue)| {
+                        let message: serde_json::Value = lua.from_value(message)?;
+                        send_outbox
+                            .borrow_mut()
+                            .push(Reply::Message { channel, message });
+                        Ok(())
+                    })
+                    .map_err(to_lua_error)?;
+                lua.globals().set("send", send).map_err(to_lua_error)?;
+
+                let backend: LuaTable = lua
+                    .load(&source)
+                    .set_name(&path.to_string_lossy().to_string())
+                    .and_then(|chunk| chunk.eval())
+                    .map_err(to_lua_error)?;
+                let tasks: LuaTable = backend.get("tasks").map_err(to_lua_error)?;
+                let poll: Option<LuaFunction> = backend.get("poll").map_err(to_lua_error)?;
+                (
+                    lua.create_registry_value(tasks).map_err(to_lua_error)?,
+                    poll.map(|poll| lua.create_registry_value(poll))
+                        .transpose()
+                        .map_err(to_lua_error)?,
+                )
+            };
+
+            Ok(Self {
+                lua,
+                tasks,
+                poll,
+                outbox,
+            })
+        }
+
+        fn call(&self, task: &Task) -> LuaResult<Option<serde_json::Value>> {
+            let tasks: LuaTable = self.lua.registry_value(&self.tasks)?;
+            let handler: Option<LuaFunction> = tasks.get(task.target.as_str())?;
+            let handler = handler
+                .ok_or_else(|| format!("no handler for task {}", task.target).to_lua_err())?;
+            let result: LuaValue = handler.call(self.lua.to_value(&task.args)?)?;
+            match result {
+                LuaValue::Nil => Ok(None),
+                result => Ok(Some(self.lua.from_value(result)?)),
+            }
+        }
+    }
+
+    impl DcsBackend for LuaBackend {
+        fn submit(&mut self, task: Task) {
+            let result = match self.call(&task) {
+                Ok(value) => TaskResultValue::Ok(value),
+                Err(e) => TaskResultValue::Error(e.to_string()),
+            };
+            self.outbox.borrow_mut().push(Reply::Result(TaskResult {
+                id: task.id,
+                result,
+            }));
+        }
+
+        fn take_replies(&mut self) -> Vec<Reply> {
+            if let Some(poll) = &self.poll {
+                let result = self
+                    .lua
+                    .registry_value::<LuaFunction>(poll)
+                    .and_then(|poll| poll.call::<_, ()>(()));
+                if let Err(e) = result {
+                    log::error!("lua backend poll failed: {}", e);
+                }
+            }
+            std::mem::take(&mut *self.outbox.borrow_mut())
+        }
+    }
+}
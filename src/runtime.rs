@@ -2,25 +2,29 @@ use std::{
     backtrace::Backtrace,
     borrow::{BorrowMut, Cow},
     cell::RefCell,
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet},
     panic,
     path::PathBuf,
     rc::Rc,
     sync::Arc,
     thread,
+    time::{Duration, Instant},
 };
 
 use deno_core::{
     anyhow::Error,
-    error::{generic_error, AnyError},
-    op_async, op_sync, CompiledWasmModuleStore, Extension, FsModuleLoader, OpState, Resource,
+    error::{generic_error, AnyError, JsError},
+    futures::task::LocalFutureObj,
+    op_async, op_sync, v8, CompiledWasmModuleStore, Extension, ModuleSpecifier, OpState, Resource,
     ResourceId,
 };
 use deno_runtime::{
     deno_broadcast_channel::InMemoryBroadcastChannel,
     deno_web::BlobStore,
     inspector_server::InspectorServer,
+    ops::worker_host::{CreateWebWorkerArgs, CreateWebWorkerCb},
     permissions::Permissions,
+    web_worker::{WebWorker, WebWorkerOptions},
     worker::{MainWorker, WorkerOptions},
     BootstrapOptions,
 };
@@ -38,7 +42,23 @@ use tokio::{
     time::timeout,
 };
 
-use crate::RUNTIME;
+use crate::{
+    backend::DcsBackend,
+    config::{Config, ReloadMode, ScriptSettings, WorkerSpec, MAIN_WORKER_NAME},
+    env::{self, WorkerEnv},
+    inspector,
+    journal::{Journal, JournalEvent},
+    kv::{KvCheck, KvEntry, KvMutation, KvStore},
+    loader::TsModuleLoader,
+    logging::DENO_TARGET,
+    permissions::{self, PermissionDenial},
+    remote::RemoteModules,
+    sdk,
+    source_maps::SourceMapStore,
+    sqlite::{Database, ExecuteResult, SqlStatement},
+    stash::Stash,
+    RUNTIME,
+};
 
 fn get_error_class_name(e: &AnyError) -> &'static str {
     deno_runtime::errors::get_error_class_name(e).unwrap_or("Error")
@@ -55,6 +75,10 @@ pub struct TaskRequest {
 #[serde(rename_all = "camelCase")]
 pub struct Task {
     pub id: u64,
+    pub worker: String,
+    /// Generation of the worker run that queued the task, Lua-side resources the
+    /// task creates are owned by it.
+    pub generation: u64,
     pub target: String,
     pub args: Option<serde_json::Value>,
 }
@@ -66,7 +90,7 @@ pub struct TaskResult {
     pub result: TaskResultValue,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(tag = "type", content = "value")]
 pub enum TaskResultValue {
     Ok(Option<serde_json::Value>),
@@ -92,93 +116,355 @@ impl UserChannelDirection {
     }
 }
 
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptError {
+    pub message: String,
+    pub stack: Option<String>,
+}
+
+/// Outcome of loading a single configured script.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptReport {
+    pub path: String,
+    pub worker: String,
+    pub loaded: bool,
+    pub error: Option<ScriptError>,
+}
+
+impl ScriptReport {
+    fn loaded(path: &str, worker: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            worker: worker.to_string(),
+            loaded: true,
+            error: None,
+        }
+    }
+
+    fn failed(path: &str, worker: &str, error: &AnyError) -> Self {
+        let error = match error.downcast_ref::<JsError>() {
+            Some(js_error) => ScriptError {
+                message: js_error.message.clone(),
+                stack: js_error.stack.clone(),
+            },
+            None => ScriptError {
+                message: error.to_string(),
+                stack: None,
+            },
+        };
+        Self {
+            path: path.to_string(),
+            worker: worker.to_string(),
+            loaded: false,
+            error: Some(error),
+        }
+    }
+}
+
+/// Load outcome of every configured script along with the bookkeeping of reloads.
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadReport {
+    pub scripts: Vec<ScriptReport>,
+    /// Tasks of retired worker generations that were dropped from the queue, or
+    /// whose results arrived after their worker was reloaded.
+    pub discarded_tasks: u64,
+}
+
 pub struct UserChannel {
     side: Either<mpsc::Sender<serde_json::Value>, mpsc::Receiver<serde_json::Value>>,
 }
 
+// bridge function unregistering the Lua-side resources of a generation, targets
+//  with this prefix are reserved for the runtime
+const INTERNAL_TARGET_PREFIX: &str = "_";
+const RELEASE_GENERATION_TARGET: &str = "_releaseGeneration";
+
+// generation of tasks queued by the runtime itself rather than a worker
+const RUNTIME_GENERATION: u64 = 0;
+
+// error of tasks still waiting for a result when the runtime stops
+const RUNTIME_STOPPED: &str = "task was discarded since the runtime stopped";
+
+// how long a reloading worker waits for the bridge to release its resources, the
+//  bridge doesn't poll while the mission is paused
+const RELEASE_GENERATION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the new worker generation of a blue/green reload did before taking over,
+/// kept back so a discarded generation leaves no trace.
+#[derive(Default)]
+struct HeldGeneration {
+    tasks: Vec<Task>,
+    // stash entries it took as (worker, key, value), restored if it is discarded
+    taken: Vec<(String, String, serde_json::Value)>,
+}
+
 pub struct Runtime {
-    task_queue: VecDeque<Task>,
-    task_waiters: HashMap<u64, Sender<TaskResultValue>>,
+    backend: Box<dyn DcsBackend + Send>,
+    task_waiters: HashMap<u64, (u64, Sender<TaskResultValue>)>,
     user_channels: HashMap<u64, UserChannel>,
     id: u64,
+    generation: u64,
+    retired_generations: HashSet<u64>,
+    held_generations: HashMap<u64, HeldGeneration>,
+    discarded_tasks: u64,
+    // isolates of the running worker generations, terminated on shutdown
+    isolates: HashMap<u64, v8::IsolateHandle>,
     config: Option<Config>,
-}
-
-#[derive(Debug, Clone, Deserialize, Serialize, Default)]
-pub struct Config {
-    pub write_dir: Option<String>,
-    pub sdk_path: Option<String>,
-    pub development: bool,
-    pub debugging: bool,
-    pub scripts: Vec<String>,
-    pub break_on_first_statement: Option<bool>,
+    inspector: Option<Arc<InspectorServer>>,
+    load_reports: HashMap<String, Vec<ScriptReport>>,
+    stash: Stash,
+    kv: Option<KvStore>,
+    journal: Option<Journal>,
+    // mission time as of the last bridge poll
+    sim_time: Option<f64>,
 }
 
 impl Runtime {
-    pub fn new(config: Config) -> Self {
-        let task_queue = VecDeque::new();
+    pub fn new(config: Config, backend: Box<dyn DcsBackend + Send>) -> Self {
         let task_waiters = HashMap::new();
         let user_channels = HashMap::new();
         Self {
-            task_queue,
+            backend,
             task_waiters,
             user_channels,
             id: 0,
+            generation: RUNTIME_GENERATION,
+            retired_generations: HashSet::new(),
+            held_generations: HashMap::new(),
+            discarded_tasks: 0,
+            isolates: HashMap::new(),
+            inspector: None,
+            load_reports: HashMap::new(),
+            stash: Stash::new(
+                config.write_dir.as_deref(),
+                &config.stash,
+                config.mission.as_ref(),
+            ),
+            kv: None,
+            journal: None,
+            sim_time: None,
             config: Some(config),
         }
     }
 
     pub fn initialize(&mut self) {
         let config = self.config.clone().unwrap();
-        thread::spawn(move || {
-            let mut rt = runtime::Runtime::new().unwrap();
-            let local = task::LocalSet::new();
-
-            local.block_on(&mut rt, async move {
-                loop {
-                    let config_copy = config.clone();
-                    match run(config_copy).await {
-                        Ok(should_reload) => {
-                            if !should_reload {
-                                return;
-                            }
-                        }
-                        Err(e) => {
-                            log::error!("error running js runtime: {}", e);
-                            return;
-                        }
-                    }
+
+        if config.journal.enabled {
+            match Journal::create(config.write_dir.as_deref().unwrap_or(".")) {
+                Ok(journal) => {
+                    journal.record(
+                        None,
+                        JournalEvent::Session {
+                            mission: config.mission.clone(),
+                        },
+                    );
+                    self.journal = Some(journal);
                 }
-            });
-        });
+                Err(e) => log::error!("failed to create journal: {}", e),
+            }
+        }
+
+        // the inspector allows arbitrary code execution, so it is only ever started in
+        //  development mode. It lives as long as the process so DevTools sessions can
+        //  reconnect to the workers created by a reload.
+        if config.development && config.inspector.enabled {
+            match inspector::start(&config.inspector) {
+                Ok(server) => self.inspector = Some(server),
+                Err(e) => log::error!("failed to start inspector server: {}", e),
+            }
+        }
+        for spec in config.worker_specs() {
+            let config = config.clone();
+            let maybe_inspector_server = self.inspector.clone();
+            thread::Builder::new()
+                .name(format!("dcs-ts-worker-{}", spec.name))
+                .spawn(move || run_worker_thread(config, spec, maybe_inspector_server, None))
+                .unwrap();
+        }
     }
 
-    pub fn get_queued_tasks<'lua>(&mut self, lua: &'lua mlua::Lua) -> Option<mlua::Value<'lua>> {
-        if self.task_queue.is_empty() {
+    pub fn get_queued_tasks<'lua>(
+        &mut self,
+        lua: &'lua mlua::Lua,
+        sim_time: Option<f64>,
+    ) -> Option<mlua::Value<'lua>> {
+        if sim_time.is_some() {
+            self.sim_time = sim_time;
+        }
+        let tasks = self.poll_tasks();
+        if tasks.is_empty() {
             return None;
         }
 
-        let tasks: Vec<Task> = self.task_queue.drain(..).collect();
         return Some(lua.to_value(&tasks).unwrap());
     }
 
+    /// Takes the tasks the backend keeps for a host pulling them, in the order
+    /// they were queued.
+    pub fn poll_tasks(&mut self) -> Vec<Task> {
+        self.backend.poll()
+    }
+
+    fn record(&self, event: JournalEvent) {
+        if let Some(journal) = &self.journal {
+            journal.record(self.sim_time, event);
+        }
+    }
+
     pub fn complete_task(&mut self, result: TaskResult) {
         log::debug!("complete_task({:?})", result);
-        if let Some(tx) = self.task_waiters.remove(&result.id) {
-            tx.send(result.result).unwrap();
+        if self.journal.is_some() {
+            self.record(JournalEvent::TaskResult {
+                id: result.id,
+                result: result.result.clone(),
+            });
+        }
+        if let Some((generation, tx)) = self.task_waiters.remove(&result.id) {
+            // the worker that queued the task was reloaded while the bridge ran it
+            if self.retired_generations.contains(&generation) || tx.send(result.result).is_err() {
+                self.discarded_tasks += 1;
+                log::debug!(
+                    "discarded result of task {} from generation {}",
+                    result.id,
+                    generation
+                );
+            }
+            self.prune_retired_generation(generation);
         }
     }
 
-    pub fn add_queued_task(&mut self, request: TaskRequest, waiter: Sender<TaskResultValue>) {
-        log::debug!("add_queued_task({:?})", request);
+    // a retired generation is only tracked while the host still runs some of its
+    //  tasks
+    fn prune_retired_generation(&mut self, generation: u64) {
+        if self.retired_generations.contains(&generation)
+            && !self.task_waiters.values().any(|(g, _)| *g == generation)
+        {
+            self.retired_generations.remove(&generation);
+        }
+    }
+
+    pub fn add_queued_task(
+        &mut self,
+        request: TaskRequest,
+        worker: String,
+        generation: u64,
+        waiter: Sender<TaskResultValue>,
+    ) {
+        log::debug!(
+            "add_queued_task({:?}, worker = {}, generation = {})",
+            request,
+            worker,
+            generation
+        );
         let id = self.id;
         self.id += 1;
-        self.task_queue.push_back(Task {
+        if self.journal.is_some() {
+            self.record(JournalEvent::TaskRequest {
+                id,
+                worker: worker.clone(),
+                target: request.target.clone(),
+                args: request.args.clone(),
+            });
+        }
+        self.task_waiters.insert(id, (generation, waiter));
+        let task = Task {
             id,
+            worker,
+            generation,
             target: request.target,
             args: request.args,
-        });
-        self.task_waiters.insert(id, waiter);
+        };
+        match self.held_generations.get_mut(&generation) {
+            Some(held) => held.tasks.push(task),
+            None => self.backend.submit(task),
+        }
+    }
+
+    /// Starts a new generation for a worker run. Every start of a worker, including
+    /// reloads, gets a new generation.
+    pub fn next_generation(&mut self) -> u64 {
+        self.generation += 1;
+        self.generation
+    }
+
+    /// Registers the isolate running `generation`, so [`shutdown`](Self::shutdown)
+    /// can terminate it.
+    pub fn add_isolate(&mut self, generation: u64, isolate: v8::IsolateHandle) {
+        self.isolates.insert(generation, isolate);
+    }
+
+    /// Terminates the scripts of every worker, their threads end once the
+    /// termination reaches them. Tasks still waiting for a result fail.
+    pub fn shutdown(&mut self) {
+        for (_, (_, tx)) in self.task_waiters.drain() {
+            let _ = tx.send(TaskResultValue::Error(RUNTIME_STOPPED.to_string()));
+        }
+        for (_, isolate) in self.isolates.drain() {
+            isolate.terminate_execution();
+        }
+    }
+
+    /// Holds back the tasks of `generation` from the backend until
+    /// [`commit_generation`](Self::commit_generation), for the new worker of a
+    /// blue/green reload which may still be discarded.
+    pub fn hold_generation(&mut self, generation: u64) {
+        self.held_generations
+            .insert(generation, HeldGeneration::default());
+    }
+
+    /// Submits the tasks held back for `generation` once it took over.
+    pub fn commit_generation(&mut self, generation: u64) {
+        if let Some(held) = self.held_generations.remove(&generation) {
+            for task in held.tasks {
+                self.backend.submit(task);
+            }
+        }
+    }
+
+    /// Retires `generation` after its worker stopped. Its tasks still queued are
+    /// dropped, results of its tasks already running are ignored, and a bridge call
+    /// unregistering every Lua-side resource (event handlers, unit watchers, menus,
+    /// marks and smoke) it owns is queued.
+    pub fn release_generation(
+        &mut self,
+        worker: String,
+        generation: u64,
+        waiter: Sender<TaskResultValue>,
+    ) {
+        self.retired_generations.insert(generation);
+        self.isolates.remove(&generation);
+
+        let mut purged = self.backend.discard(generation);
+        // a discarded blue/green generation never reached the backend
+        if let Some(held) = self.held_generations.remove(&generation) {
+            purged.extend(held.tasks.iter().map(|task| task.id));
+            for (worker, key, value) in held.taken {
+                self.stash.restore(&worker, key, value);
+            }
+        }
+        for id in &purged {
+            self.task_waiters.remove(id);
+        }
+        let purged = purged.len();
+        self.discarded_tasks += purged as u64;
+        self.prune_retired_generation(generation);
+        log::info!(
+            "retired generation {} of worker {}, purged {} queued tasks ({} discarded in total)",
+            generation,
+            worker,
+            purged,
+            self.discarded_tasks
+        );
+
+        let request = TaskRequest {
+            target: RELEASE_GENERATION_TARGET.to_string(),
+            args: Some(json!({ "generation": generation })),
+        };
+        self.add_queued_task(request, worker, RUNTIME_GENERATION, waiter);
     }
 
     pub fn add_user_channel(
@@ -194,6 +480,12 @@ impl Runtime {
     }
 
     pub fn send_user_channel_message(&mut self, id: u64, message: serde_json::Value) -> bool {
+        if self.journal.is_some() {
+            self.record(JournalEvent::ChannelMessage {
+                channel: id,
+                message: message.clone(),
+            });
+        }
         if let Some(user_channel) = self.user_channels.get(&id) {
             if let Either::Left(tx) = &user_channel.side {
                 return tx.try_send(message).is_ok();
@@ -202,15 +494,62 @@ impl Runtime {
         return false;
     }
 
+    /// Takes the messages the scripts sent to Lua on channel `id`.
+    pub fn receive_user_channel_messages(&mut self, id: u64) -> Vec<serde_json::Value> {
+        let mut messages = vec![];
+        if let Some(user_channel) = self.user_channels.get_mut(&id) {
+            if let Either::Right(rx) = &mut user_channel.side {
+                while let Ok(message) = rx.try_recv() {
+                    messages.push(message);
+                }
+            }
+        }
+        messages
+    }
+
+    fn record_user_channel_send(&self, id: u64, message: &serde_json::Value) {
+        if self.journal.is_some() {
+            self.record(JournalEvent::ChannelSend {
+                channel: id,
+                message: message.clone(),
+            });
+        }
+    }
+
     pub fn remove_user_channel(&mut self, id: u64) {
         self.user_channels.remove(&id);
     }
-}
 
-impl<'lua> mlua::FromLua<'lua> for Config {
-    fn from_lua(lua_value: mlua::Value<'lua>, lua: &'lua mlua::Lua) -> mlua::Result<Self> {
-        let config: Config = lua.from_value(lua_value)?;
-        Ok(config)
+    /// Takes the stash entry `key` of `worker` for `generation`. Entries taken by
+    /// a held generation are restored if it is discarded.
+    pub fn take_stash(
+        &mut self,
+        worker: &str,
+        generation: u64,
+        key: &str,
+    ) -> Option<serde_json::Value> {
+        let value = self.stash.take(worker, key)?;
+        if let Some(held) = self.held_generations.get_mut(&generation) {
+            held.taken
+                .push((worker.to_string(), key.to_string(), value.clone()));
+        }
+        Some(value)
+    }
+
+    pub fn set_load_report(&mut self, worker: &str, reports: Vec<ScriptReport>) {
+        self.load_reports.insert(worker.to_string(), reports);
+    }
+
+    /// Returns the load outcome of every configured script, as of the last time
+    /// its worker was (re)loaded.
+    pub fn load_report(&self) -> LoadReport {
+        let mut scripts: Vec<ScriptReport> =
+            self.load_reports.values().flatten().cloned().collect();
+        scripts.sort_by(|a, b| a.worker.cmp(&b.worker));
+        LoadReport {
+            scripts,
+            discarded_tasks: self.discarded_tasks,
+        }
     }
 }
 
@@ -221,20 +560,258 @@ impl<'lua> mlua::FromLua<'lua> for TaskResult {
     }
 }
 
-async fn op_dcs_run_queued_task(
+/// Identifies the worker an op is being called from.
+#[derive(Clone)]
+pub struct WorkerInfo {
+    pub name: String,
+    pub spec: Option<WorkerSpec>,
+    pub generation: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct StashPut {
+    key: String,
+    value: serde_json::Value,
+}
+
+fn op_dcs_stash_put(state: &mut OpState, args: StashPut, _: ()) -> Result<(), Error> {
+    let worker = state.borrow::<WorkerInfo>().name.clone();
+    let mut runtime = RUNTIME.lock().unwrap();
+    match runtime.as_mut() {
+        Some(runtime) => runtime.stash.put(&worker, args.key, args.value),
+        None => Err(generic_error("invalid runtime")),
+    }
+}
+
+fn op_dcs_stash_take(
+    state: &mut OpState,
+    key: String,
+    _: (),
+) -> Result<Option<serde_json::Value>, Error> {
+    let worker = state.borrow::<WorkerInfo>();
+    let mut runtime = RUNTIME.lock().unwrap();
+    Ok(runtime
+        .as_mut()
+        .and_then(|runtime| runtime.take_stash(&worker.name, worker.generation, &key)))
+}
+
+/// Returns the key-value store, opening it on first use. The store is opened
+/// without holding the runtime, which the bridge polls every frame.
+fn runtime_kv() -> Result<KvStore, Error> {
+    let write_dir = {
+        let runtime = RUNTIME.lock().unwrap();
+        let runtime = runtime
+            .as_ref()
+            .ok_or_else(|| generic_error("invalid runtime"))?;
+        if let Some(kv) = &runtime.kv {
+            return Ok(kv.clone());
+        }
+        runtime
+            .config
+            .as_ref()
+            .and_then(|config| config.write_dir.clone())
+            .ok_or_else(|| generic_error("no write dir configured"))?
+    };
+
+    let kv = KvStore::open(&write_dir)?;
+    let mut runtime = RUNTIME.lock().unwrap();
+    let runtime = runtime
+        .as_mut()
+        .ok_or_else(|| generic_error("invalid runtime"))?;
+    // another worker may have opened it meanwhile
+    Ok(runtime.kv.get_or_insert(kv).clone())
+}
+
+// the store is synchronous, so its calls (and opening it) run on the blocking pool
+//  instead of the event loop
+async fn run_kv<T, F>(f: F) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: FnOnce(KvStore) -> Result<T, Error> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || f(runtime_kv()?)).await?
+}
+
+async fn op_dcs_kv_get(
+    _state: Rc<RefCell<OpState>>,
+    key: String,
+    _: (),
+) -> Result<Option<serde_json::Value>, Error> {
+    run_kv(move |kv| kv.get(&key)).await
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct KvSet {
+    key: String,
+    value: serde_json::Value,
+}
+
+async fn op_dcs_kv_set(_state: Rc<RefCell<OpState>>, args: KvSet, _: ()) -> Result<(), Error> {
+    run_kv(move |kv| kv.set(&args.key, &args.value)).await
+}
+
+async fn op_dcs_kv_delete(_state: Rc<RefCell<OpState>>, key: String, _: ()) -> Result<(), Error> {
+    run_kv(move |kv| kv.delete(&key)).await
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct KvList {
+    prefix: String,
+    limit: Option<usize>,
+}
+
+async fn op_dcs_kv_list(
+    _state: Rc<RefCell<OpState>>,
+    args: KvList,
+    _: (),
+) -> Result<Vec<KvEntry>, Error> {
+    run_kv(move |kv| kv.list(&args.prefix, args.limit)).await
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct KvAtomic {
+    #[serde(default)]
+    checks: Vec<KvCheck>,
+    mutations: Vec<KvMutation>,
+}
+
+async fn op_dcs_kv_atomic(
     _state: Rc<RefCell<OpState>>,
+    args: KvAtomic,
+    _: (),
+) -> Result<bool, Error> {
+    run_kv(move |kv| kv.atomic(&args.checks, &args.mutations)).await
+}
+
+async fn op_dcs_sqlite_open(
+    state: Rc<RefCell<OpState>>,
+    path: String,
+    _: (),
+) -> Result<ResourceId, Error> {
+    let write_dir = {
+        let runtime = RUNTIME.lock().unwrap();
+        runtime
+            .as_ref()
+            .and_then(|runtime| runtime.config.as_ref())
+            .and_then(|config| config.write_dir.clone())
+            .ok_or_else(|| generic_error("no write dir configured"))?
+    };
+    let mut data_dir = PathBuf::from(write_dir);
+    data_dir.push("Data");
+
+    // opening creates the file and reads its header, which blocks
+    let database = tokio::task::spawn_blocking(move || Database::open(&data_dir, &path)).await??;
+    Ok(state.borrow_mut().resource_table.add(database))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SqliteStatement {
+    rid: ResourceId,
+    #[serde(flatten)]
+    statement: SqlStatement,
+}
+
+async fn op_dcs_sqlite_execute(
+    state: Rc<RefCell<OpState>>,
+    args: SqliteStatement,
+    _: (),
+) -> Result<ExecuteResult, Error> {
+    let database = state.borrow().resource_table.get::<Database>(args.rid)?;
+    database.execute(args.statement).await
+}
+
+async fn op_dcs_sqlite_query(
+    state: Rc<RefCell<OpState>>,
+    args: SqliteStatement,
+    _: (),
+) -> Result<Vec<serde_json::Map<String, serde_json::Value>>, Error> {
+    let database = state.borrow().resource_table.get::<Database>(args.rid)?;
+    database.query(args.statement).await
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SqliteTransaction {
+    rid: ResourceId,
+    statements: Vec<SqlStatement>,
+}
+
+async fn op_dcs_sqlite_transaction(
+    state: Rc<RefCell<OpState>>,
+    args: SqliteTransaction,
+    _: (),
+) -> Result<Vec<ExecuteResult>, Error> {
+    let database = state.borrow().resource_table.get::<Database>(args.rid)?;
+    database.transaction(args.statements).await
+}
+
+fn op_dcs_permission_denied(
+    state: &mut OpState,
+    denial: PermissionDenial,
+    _: (),
+) -> Result<(), Error> {
+    let worker = state.borrow::<WorkerInfo>();
+    let scripts: Vec<String> = worker
+        .spec
+        .iter()
+        .flat_map(|spec| spec.scripts.iter())
+        .filter_map(|script| deno_core::resolve_path(script.path()).ok())
+        .map(|module| module.to_string())
+        .collect();
+    permissions::log_denial(&worker.name, &denial, &scripts);
+    Ok(())
+}
+
+fn op_dcs_script_options(
+    state: &mut OpState,
+    specifier: Option<String>,
+    _: (),
+) -> Result<ScriptSettings, Error> {
+    let worker = state.borrow::<WorkerInfo>();
+    worker
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.script_settings(specifier.as_deref()))
+        .ok_or_else(|| {
+            generic_error(format!(
+                "no script {} configured for worker {}",
+                specifier.as_deref().unwrap_or("<unknown>"),
+                worker.name
+            ))
+        })
+}
+
+async fn op_dcs_run_queued_task(
+    state: Rc<RefCell<OpState>>,
     request: TaskRequest,
     _: (),
 ) -> Result<serde_json::Value, Error> {
+    if request.target.starts_with(INTERNAL_TARGET_PREFIX) {
+        return Err(generic_error(format!(
+            "task target {} is reserved",
+            request.target
+        )));
+    }
+
+    let worker = state.borrow().borrow::<WorkerInfo>().clone();
     let (tx, rx) = oneshot::channel();
     {
         let mut runtime = RUNTIME.lock().unwrap();
-        if runtime.is_some() {
-            runtime.as_mut().unwrap().add_queued_task(request, tx);
+        match runtime.as_mut() {
+            Some(runtime) => runtime.add_queued_task(request, worker.name, worker.generation, tx),
+            None => return Err(generic_error(RUNTIME_STOPPED)),
         }
     }
 
-    match rx.await.unwrap() {
+    match rx
+        .await
+        .map_err(|_| generic_error("task was discarded by a reload"))?
+    {
         TaskResultValue::Ok(value) => Ok(value.unwrap_or(json!(null))),
         TaskResultValue::Error(message) => Err(generic_error(message)),
     }
@@ -305,6 +882,38 @@ async fn op_dcs_user_channel_wait(
     }
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct UserChannelSend {
+    channel: UserChannelHandle,
+    value: serde_json::Value,
+}
+
+async fn op_dcs_user_channel_send(
+    state: Rc<RefCell<OpState>>,
+    user_channel_send: UserChannelSend,
+    _: (),
+) -> Result<(), Error> {
+    let user_channel = state
+        .borrow()
+        .resource_table
+        .get::<UserChannelResource>(user_channel_send.channel.resource_id)?;
+
+    let tx = match &*user_channel.side.try_borrow()? {
+        Either::Left(tx) => tx.clone(),
+        Either::Right(_) => return Err(generic_error("cannot send on a receiver channel")),
+    };
+    {
+        let runtime = RUNTIME.lock().unwrap();
+        if let Some(runtime) = runtime.as_ref() {
+            runtime.record_user_channel_send(user_channel.id, &user_channel_send.value);
+        }
+    }
+    tx.send(user_channel_send.value)
+        .await
+        .map_err(|_| generic_error("channel was closed"))
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct CreateUserChannel {
@@ -370,30 +979,68 @@ pub fn op_print(_state: &mut OpState, msg: String, is_err: bool) -> Result<(), E
     let mut msg_clean = msg.clone();
     trim_newline(&mut msg_clean);
     if is_err {
-        log::error!("[deno] {}", msg_clean);
+        log::error!(target: DENO_TARGET, "[deno] {}", msg_clean);
     } else {
-        log::info!("[deno] {}", msg_clean);
+        log::info!(target: DENO_TARGET, "[deno] {}", msg_clean);
     }
     Ok(())
 }
 
-async fn run(config: Config) -> Result<bool, Error> {
-    let (reload_tx, mut reload_rx) = mpsc::channel::<()>(1);
-
-    let ext = Extension::builder()
+/// Builds the extension providing the DCS ops, shared by main and web workers.
+fn dcs_extension(worker: WorkerInfo) -> Extension {
+    Extension::builder()
         .middleware(|name, opfn| match name {
             "op_print" => op_sync(op_print),
+            // `Deno.env` works on the environment of the worker, the process
+            //  environment is shared by every worker and never modified
+            "op_env" => op_sync(env::op_env),
+            "op_get_env" => op_sync(env::op_get_env),
+            "op_set_env" => op_sync(env::op_set_env),
+            "op_delete_env" => op_sync(env::op_delete_env),
             _ => opfn,
         })
+        .js(vec![(
+            "dcs:ext/permissions.js",
+            Box::new(|| Ok(permissions::DENIAL_HOOK.to_string())),
+        )])
         .ops(vec![
+            (
+                "op_dcs_load_report",
+                op_sync(|_state, _: (), _: ()| {
+                    let runtime = RUNTIME.lock().unwrap();
+                    Ok(runtime
+                        .as_ref()
+                        .map(|runtime| runtime.load_report())
+                        .unwrap_or_default())
+                }),
+            ),
             (
                 "op_dcs_print",
                 op_sync(|_state, message: String, _: ()| {
-                    log::info!("[deno] {}", message);
+                    log::info!(target: DENO_TARGET, "[deno] {}", message);
                     Ok(())
                 }),
             ),
             ("op_dcs_run_queued_task", op_async(op_dcs_run_queued_task)),
+            ("op_dcs_script_options", op_sync(op_dcs_script_options)),
+            (
+                "op_dcs_permission_denied",
+                op_sync(op_dcs_permission_denied),
+            ),
+            ("op_dcs_stash_put", op_sync(op_dcs_stash_put)),
+            ("op_dcs_stash_take", op_sync(op_dcs_stash_take)),
+            ("op_dcs_kv_get", op_async(op_dcs_kv_get)),
+            ("op_dcs_kv_set", op_async(op_dcs_kv_set)),
+            ("op_dcs_kv_delete", op_async(op_dcs_kv_delete)),
+            ("op_dcs_kv_list", op_async(op_dcs_kv_list)),
+            ("op_dcs_kv_atomic", op_async(op_dcs_kv_atomic)),
+            ("op_dcs_sqlite_open", op_async(op_dcs_sqlite_open)),
+            ("op_dcs_sqlite_execute", op_async(op_dcs_sqlite_execute)),
+            ("op_dcs_sqlite_query", op_async(op_dcs_sqlite_query)),
+            (
+                "op_dcs_sqlite_transaction",
+                op_async(op_dcs_sqlite_transaction),
+            ),
             (
                 "op_dcs_create_user_channel",
                 op_sync(op_dcs_create_user_channel),
@@ -402,59 +1049,315 @@ async fn run(config: Config) -> Result<bool, Error> {
                 "op_dcs_user_channel_wait",
                 op_async(op_dcs_user_channel_wait),
             ),
+            (
+                "op_dcs_user_channel_send",
+                op_async(op_dcs_user_channel_send),
+            ),
             (
                 "op_dcs_reload",
                 op_sync(|state: &mut OpState, reloader_id: ResourceId, _: ()| {
                     log::info!("user requested reload");
                     state
                         .resource_table
-                        .get::<ReloaderResource>(reloader_id)
-                        .unwrap()
+                        .get::<ReloaderResource>(reloader_id)?
                         .borrow_mut()
                         .tx
                         .try_send(())
-                        .unwrap();
+                        .map_err(|_| generic_error("reload already in progress"))?;
                     Ok(())
                 }),
             ),
         ])
-        .build();
+        .state(move |state| {
+            let vars = worker
+                .spec
+                .as_ref()
+                .map(|spec| spec.env.clone())
+                .unwrap_or_default();
+            state.put(WorkerEnv::new(vars));
+            state.put(worker.clone());
+            Ok(())
+        })
+        .build()
+}
+
+fn create_module_loader(config: &Config, source_maps: SourceMapStore) -> Rc<TsModuleLoader> {
+    let import_map =
+        config
+            .import_map
+            .as_ref()
+            .and_then(|path| match TsModuleLoader::load_import_map(path) {
+                Ok(import_map) => Some(import_map),
+                Err(e) => {
+                    log::error!("{}", e);
+                    None
+                }
+            });
+    let cache_dir = config.write_dir.as_ref().map(|write_dir| {
+        let mut cache_dir = PathBuf::from(write_dir);
+        cache_dir.push("Cache/ts/compiled");
+        cache_dir
+    });
+    let remote = config
+        .write_dir
+        .as_ref()
+        .map(|write_dir| RemoteModules::new(write_dir, &config.modules));
+    Rc::new(TsModuleLoader::new(
+        import_map,
+        cache_dir,
+        remote,
+        source_maps,
+    ))
+}
+
+fn bootstrap_options(args: Vec<String>) -> BootstrapOptions {
+    BootstrapOptions {
+        apply_source_maps: true,
+        args,
+        cpu_count: 1,
+        debug_flag: true,
+        enable_testing_features: false,
+        location: None,
+        no_color: true,
+        runtime_version: "x".to_string(),
+        ts_version: "x".to_string(),
+        unstable: true,
+    }
+}
+
+/// Creates the callback used to spawn web workers (`new Worker(...)`). Web workers
+/// run on their own thread and get the DCS ops, so tasks and channels can be used
+/// from them like from the worker that created them. They also see the scripts of
+/// the worker that created them, for `getScriptOptions`.
+fn create_web_worker_callback(parent: WorkerInfo, config: Config) -> Arc<CreateWebWorkerCb> {
+    Arc::new(move |args: CreateWebWorkerArgs| {
+        let worker_name = format!("{}/{}", parent.name, args.name);
+        log::debug!("creating web worker {} ({})", worker_name, args.main_module);
+        let source_maps = SourceMapStore::default();
+
+        let worker = WorkerInfo {
+            name: worker_name,
+            spec: parent.spec.clone(),
+            generation: parent.generation,
+        };
+
+        let options = WebWorkerOptions {
+            bootstrap: bootstrap_options(vec![]),
+            extensions: vec![dcs_extension(worker.clone())],
+            unsafely_ignore_certificate_errors: None,
+            root_cert_store: None,
+            user_agent: "DCSTS".to_string(),
+            seed: None,
+            module_loader: create_module_loader(&config, source_maps.clone()),
+            create_web_worker_cb: create_web_worker_callback(worker, config.clone()),
+            preload_module_cb: Arc::new(|worker: WebWorker| {
+                LocalFutureObj::new(Box::new(async move { Ok(worker) }))
+            }),
+            js_error_create_fn: Some(source_maps.error_create_fn()),
+            use_deno_namespace: args.use_deno_namespace,
+            worker_type: args.worker_type,
+            maybe_inspector_server: None,
+            get_error_class_fn: Some(&get_error_class_name),
+            blob_store: BlobStore::default(),
+            broadcast_channel: InMemoryBroadcastChannel::default(),
+            shared_array_buffer_store: None,
+            compiled_wasm_module_store: Some(CompiledWasmModuleStore::default()),
+        };
+
+        WebWorker::bootstrap_from_options(
+            args.name,
+            args.permissions,
+            args.main_module,
+            args.worker_id,
+            options,
+        )
+    })
+}
+
+/// Runs the worker described by `spec` on the current thread, recreating it every
+/// time it requests a reload.
+fn run_worker_thread(
+    config: Config,
+    spec: WorkerSpec,
+    maybe_inspector_server: Option<Arc<InspectorServer>>,
+    mut handoff: Option<Handoff>,
+) {
+    let mut rt = runtime::Runtime::new().unwrap();
+    let local = task::LocalSet::new();
+
+    local.block_on(&mut rt, async move {
+        loop {
+            let generation = {
+                let mut runtime = RUNTIME.lock().unwrap();
+                match runtime.as_mut() {
+                    Some(runtime) => {
+                        let generation = runtime.next_generation();
+                        // DCS must not see the new worker before it takes over
+                        if handoff.is_some() {
+                            runtime.hold_generation(generation);
+                        }
+                        generation
+                    }
+                    None => return,
+                }
+            };
 
-    let module_loader = Rc::new(FsModuleLoader);
-    let create_web_worker_cb = Arc::new(|_| {
-        todo!("no web workers");
+            let config_copy = config.clone();
+            let result = run(
+                config_copy,
+                spec.clone(),
+                generation,
+                maybe_inspector_server.clone(),
+                handoff.take(),
+            )
+            .await;
+            match result {
+                Ok(should_reload) => {
+                    if !should_reload {
+                        return;
+                    }
+                    release_generation(&spec.name, generation).await;
+                }
+                Err(e) => {
+                    log::error!("error running js runtime for worker {}: {}", spec.name, e);
+                    return;
+                }
+            }
+        }
     });
+}
+
+/// Handed to the new worker of a blue/green reload. It reports whether it loaded
+/// through `ready` and only keeps running if the old worker confirms the switch
+/// through `commit`.
+struct Handoff {
+    ready: oneshot::Sender<Result<(), String>>,
+    commit: oneshot::Receiver<()>,
+}
+
+/// A blue/green reload in progress, seen from the old worker.
+struct PendingReload {
+    ready: oneshot::Receiver<Result<(), String>>,
+    commit: oneshot::Sender<()>,
+    deadline: tokio::time::Instant,
+}
+
+impl PendingReload {
+    /// Boots the next generation of `spec` on a new thread, next to the current one.
+    fn start(
+        config: &Config,
+        spec: &WorkerSpec,
+        maybe_inspector_server: Option<Arc<InspectorServer>>,
+    ) -> Self {
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let (commit_tx, commit_rx) = oneshot::channel();
+        let handoff = Handoff {
+            ready: ready_tx,
+            commit: commit_rx,
+        };
+        let (config, spec) = (config.clone(), spec.clone());
+        let deadline =
+            tokio::time::Instant::now() + Duration::from_secs(config.reload.ready_timeout_secs);
+        thread::Builder::new()
+            .name(format!("dcs-ts-worker-{}", spec.name))
+            .spawn(move || run_worker_thread(config, spec, maybe_inspector_server, Some(handoff)))
+            .unwrap();
 
-    let server = Arc::new(InspectorServer::new(
-        "0.0.0.0:50009".parse().unwrap(),
-        "DCSTS".to_string(),
-    ));
+        Self {
+            ready: ready_rx,
+            commit: commit_tx,
+            deadline,
+        }
+    }
+}
 
-    let mut origin_storage_dir = PathBuf::from(&config.write_dir.unwrap());
+/// Waits for the new worker of a pending blue/green reload to load, never
+/// completing if there is none.
+async fn wait_ready(pending: &mut Option<PendingReload>) -> Result<(), String> {
+    let pending = match pending {
+        Some(pending) => pending,
+        None => return futures_util::future::pending().await,
+    };
+    match tokio::time::timeout_at(pending.deadline, &mut pending.ready).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(_)) => Err("the new worker stopped while loading".to_string()),
+        Err(_) => Err("timed out waiting for the new worker to load".to_string()),
+    }
+}
+
+/// Lets scripts save their state (e.g. into the stash) before the worker is
+/// replaced, in either reload mode.
+fn dispatch_reload_event(worker: &mut MainWorker) {
+    if let Err(e) = worker.execute_script(
+        "<reload>",
+        "globalThis.dispatchEvent(new Event(\"reload\"));",
+    ) {
+        log::error!("error dispatching reload event: {}", e);
+    }
+}
+
+/// Has the bridge unregister the Lua-side resources created by a worker run before
+/// the worker is started again.
+async fn release_generation(worker: &str, generation: u64) {
+    let (tx, rx) = oneshot::channel();
+    {
+        let mut runtime = RUNTIME.lock().unwrap();
+        match runtime.as_mut() {
+            Some(runtime) => runtime.release_generation(worker.to_string(), generation, tx),
+            None => return,
+        }
+    }
+
+    match timeout(RELEASE_GENERATION_TIMEOUT, rx).await {
+        Ok(Ok(TaskResultValue::Ok(released))) => log::info!(
+            "released {} lua resources of worker {} (generation {})",
+            released.unwrap_or(json!(0)),
+            worker,
+            generation
+        ),
+        Ok(Ok(TaskResultValue::Error(e))) => log::error!(
+            "failed to release lua resources of worker {}: {}",
+            worker,
+            e
+        ),
+        Ok(Err(_)) | Err(_) => log::warn!(
+            "timed out releasing lua resources of worker {}, continuing the reload",
+            worker
+        ),
+    }
+}
+
+async fn run(
+    config: Config,
+    spec: WorkerSpec,
+    generation: u64,
+    maybe_inspector_server: Option<Arc<InspectorServer>>,
+    handoff: Option<Handoff>,
+) -> Result<bool, Error> {
+    let (reload_tx, mut reload_rx) = mpsc::channel::<()>(1);
+
+    let source_maps = SourceMapStore::default();
+    let module_loader = create_module_loader(&config, source_maps.clone());
+    let worker_info = WorkerInfo {
+        name: spec.name.clone(),
+        spec: Some(spec.clone()),
+        generation,
+    };
+    let create_web_worker_cb = create_web_worker_callback(worker_info.clone(), config.clone());
+
+    let mut origin_storage_dir = PathBuf::from(config.write_dir.as_ref().unwrap());
     origin_storage_dir.push("Data/");
 
     let options = WorkerOptions {
-        bootstrap: BootstrapOptions {
-            apply_source_maps: true,
-            args: vec![],
-            cpu_count: 1,
-            debug_flag: true,
-            enable_testing_features: false,
-            location: None,
-            no_color: true,
-            runtime_version: "x".to_string(),
-            ts_version: "x".to_string(),
-            unstable: true,
-        },
-        extensions: vec![ext],
+        bootstrap: bootstrap_options(spec.args.clone()),
+        extensions: vec![dcs_extension(worker_info)],
         unsafely_ignore_certificate_errors: None,
         root_cert_store: None,
         user_agent: "DCSTS".to_string(),
         seed: None,
-        js_error_create_fn: None,
+        js_error_create_fn: Some(source_maps.error_create_fn()),
         create_web_worker_cb,
-        // for some reason this doesn't work
-        maybe_inspector_server: None,
+        maybe_inspector_server: maybe_inspector_server.clone(),
         should_break_on_first_statement: config.break_on_first_statement.unwrap_or(false),
         module_loader,
         get_error_class_fn: Some(&get_error_class_name),
@@ -464,10 +1367,12 @@ async fn run(config: Config) -> Result<bool, Error> {
         shared_array_buffer_store: None,
         compiled_wasm_module_store: Some(CompiledWasmModuleStore::default()),
     };
-    let permissions = Permissions::allow_all();
+    let permissions: Permissions = spec.permissions.to_permissions();
 
-    let sdk_module = if let Some(sdk_path) = config.sdk_path {
-        match deno_core::resolve_path(&sdk_path) {
+    // the sdk is embedded in the binary, `sdk_path` can still override it with a
+    //  bundled build
+    let sdk_module = if let Some(sdk_path) = &config.sdk_path {
+        match deno_core::resolve_path(sdk_path) {
             Ok(sdk_module) => sdk_module,
             Err(e) => {
                 log::error!("error resolving sdk module: {:#?}", e);
@@ -475,10 +1380,55 @@ async fn run(config: Config) -> Result<bool, Error> {
             }
         }
     } else {
-        deno_core::resolve_path("sdk.js").unwrap()
+        sdk::main_module()
+    };
+
+    let modules: Vec<(&str, Result<ModuleSpecifier, AnyError>)> = spec
+        .scripts
+        .iter()
+        .map(|script| {
+            (
+                script.path(),
+                deno_core::resolve_path(script.path()).map_err(AnyError::from),
+            )
+        })
+        .collect();
+
+    // the main module identifies the worker to the inspector, isolated workers use
+    //  their first script so that each of them gets a distinct target
+    let main_module = if spec.name == MAIN_WORKER_NAME {
+        sdk_module.clone()
+    } else {
+        modules
+            .iter()
+            .find_map(|(_, module)| module.as_ref().ok().cloned())
+            .unwrap_or_else(|| sdk_module.clone())
     };
 
-    let mut worker = MainWorker::bootstrap_from_options(sdk_module.clone(), permissions, options);
+    if maybe_inspector_server.is_some() {
+        log::info!(
+            "inspector target for worker {} is /ws/{}",
+            spec.name,
+            inspector::target_id(main_module.as_str())
+        );
+    }
+
+    let started_at = Instant::now();
+    let mut worker = MainWorker::bootstrap_from_options(main_module.clone(), permissions, options);
+    let bootstrap_time = started_at.elapsed();
+    if maybe_inspector_server.is_some() {
+        inspector::target_registered();
+    }
+    {
+        let isolate = worker.js_runtime.v8_isolate().thread_safe_handle();
+        match RUNTIME.lock().unwrap().as_mut() {
+            Some(runtime) => runtime.add_isolate(generation, isolate),
+            // stopped while bootstrapping
+            None => {
+                isolate.terminate_execution();
+            }
+        }
+    }
 
     panic::set_hook(Box::new(|_panic_info| {
         let backtrace = Backtrace::force_capture();
@@ -499,54 +1449,264 @@ async fn run(config: Config) -> Result<bool, Error> {
         .execute_script("<reloader>", &reloader_script)
         .unwrap();
 
-    if config.development {
-        // manually register inspector and create a new session
-        server.register_inspector(sdk_module.to_string(), &mut worker.js_runtime, false);
-        worker.create_inspector_session().await;
-    }
-
-    if sdk_module.path() != "file:///sdk.js" {
+    let sdk_started_at = Instant::now();
+    if sdk_module == main_module {
         worker.execute_main_module(&sdk_module).await?;
+    } else {
+        worker.execute_side_module(&sdk_module).await?;
     }
+    let sdk_time = sdk_started_at.elapsed();
 
-    let modules =
-        config
-            .scripts
-            .iter()
-            .map(|script_path| match deno_core::resolve_path(&script_path) {
-                Ok(main_module) => Some(main_module),
-                Err(e) => {
-                    log::error!("error resolving script path: {:#?} ({})", e, script_path);
-                    return None;
-                }
-            });
+    // every script is loaded on its own, a failing script is reported and skipped
+    //  without affecting the others
+    let mut reports = Vec::new();
+    for (path, module) in modules {
+        let result = match module {
+            Ok(module) if module == main_module => worker.execute_main_module(&module).await,
+            Ok(module) => worker.execute_side_module(&module).await,
+            Err(e) => Err(e),
+        };
+        let report = match result {
+            Ok(_) => ScriptReport::loaded(path, &spec.name),
+            Err(e) => {
+                log::error!("error loading script {}: {}", path, e);
+                ScriptReport::failed(path, &spec.name, &e)
+            }
+        };
+        reports.push(report);
+    }
 
-    for maybe_module in modules {
-        if let Some(module) = maybe_module {
-            worker.execute_side_module(&module).await?;
+    let failed: Vec<String> = reports
+        .iter()
+        .filter(|report| !report.loaded)
+        .map(|report| report.path.clone())
+        .collect();
+    {
+        let mut runtime = RUNTIME.lock().unwrap();
+        if runtime.is_some() {
+            runtime
+                .as_mut()
+                .unwrap()
+                .set_load_report(&spec.name, reports);
         }
     }
 
     worker.dispatch_load_event("")?;
 
-    tokio::select! {
-        result = worker.run_event_loop(true) => {
-            match result {
-                Ok(_) => {
-                    log::info!("done running js loop");
+    // the new worker of a blue/green reload only takes over if everything loaded
+    //  and the old worker is still waiting for it, otherwise it is discarded
+    if let Some(handoff) = handoff {
+        let ready = if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("failed to load {}", failed.join(", ")))
+        };
+        let loaded = ready.is_ok();
+        if handoff.ready.send(ready).is_err() || !loaded || handoff.commit.await.is_err() {
+            log::warn!(
+                "discarding generation {} of worker {}",
+                generation,
+                spec.name
+            );
+            worker.dispatch_unload_event("")?;
+            release_generation(&spec.name, generation).await;
+            return Ok(false);
+        }
+        if let Some(runtime) = RUNTIME.lock().unwrap().as_mut() {
+            runtime.commit_generation(generation);
+        }
+        log::info!("worker {} switched to generation {}", spec.name, generation);
+    }
+    log::info!(
+        "worker {} started in {:?} (bootstrap {:?}, sdk {:?}, scripts {:?})",
+        spec.name,
+        started_at.elapsed(),
+        bootstrap_time,
+        sdk_time,
+        started_at.elapsed() - bootstrap_time - sdk_time
+    );
+
+    let mut pending: Option<PendingReload> = None;
+    let mut event_loop_done = false;
+    loop {
+        tokio::select! {
+            result = worker.run_event_loop(true), if !event_loop_done => {
+                match result {
+                    Ok(_) => {
+                        log::info!("done running js loop");
+                    }
+                    Err(error) => {
+                        log::error!("error running js loop: {}", error);
+                    }
                 }
-                Err(error) => {
-                    log::error!("error running js loop: {}", error);
+                if pending.is_none() {
+                    break;
+                }
+                // the new worker of a pending reload can still take over
+                event_loop_done = true;
+            }
+            _ = reload_rx.recv() => {
+                log::info!("reload requested for worker {}", spec.name);
+                if pending.is_some() {
+                    log::warn!("reload of worker {} is already in progress", spec.name);
+                    continue;
+                }
+                dispatch_reload_event(&mut worker);
+                if config.reload.mode == ReloadMode::InPlace {
+                    worker.dispatch_unload_event("")?;
+                    return Ok(true);
+                }
+                // the old worker keeps running until the new one is ready
+                pending = Some(PendingReload::start(
+                    &config,
+                    &spec,
+                    maybe_inspector_server.clone(),
+                ));
+            }
+            ready = wait_ready(&mut pending) => {
+                let PendingReload { commit, .. } = pending.take().unwrap();
+                let result = ready.and_then(|_| {
+                    commit
+                        .send(())
+                        .map_err(|_| "the new worker stopped before taking over".to_string())
+                });
+                match result {
+                    Ok(()) => {
+                        worker.dispatch_unload_event("")?;
+                        release_generation(&spec.name, generation).await;
+                        return Ok(false);
+                    }
+                    Err(e) => log::error!(
+                        "reload of worker {} failed, keeping generation {} running: {}",
+                        spec.name,
+                        generation,
+                        e
+                    ),
+                }
+                if event_loop_done {
+                    break;
                 }
             }
-        }
-        _ = reload_rx.recv() => {
-            log::info!("reload requested");
-            worker.dispatch_unload_event("").unwrap();
-            return Ok(true);
         }
     }
 
     log::info!("done");
     return Ok(false);
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::oneshot::error::TryRecvError;
+
+    use super::*;
+    use crate::backend::QueueBackend;
+
+    fn runtime() -> Runtime {
+        Runtime::new(Config::default(), Box::new(QueueBackend::default()))
+    }
+
+    fn queue(
+        runtime: &mut Runtime,
+        target: &str,
+        generation: u64,
+    ) -> oneshot::Receiver<TaskResultValue> {
+        let (tx, rx) = oneshot::channel();
+        let request = TaskRequest {
+            target: target.to_string(),
+            args: None,
+        };
+        runtime.add_queued_task(request, "main".to_string(), generation, tx);
+        rx
+    }
+
+    fn targets(tasks: &[Task]) -> Vec<&str> {
+        tasks.iter().map(|task| task.target.as_str()).collect()
+    }
+
+    fn ok(id: u64) -> TaskResult {
+        TaskResult {
+            id,
+            result: TaskResultValue::Ok(None),
+        }
+    }
+
+    #[test]
+    fn released_generations_are_purged() {
+        let mut runtime = runtime();
+        let old = runtime.next_generation();
+        let new = runtime.next_generation();
+
+        let mut running = queue(&mut runtime, "running", old);
+        let running_id = runtime.poll_tasks()[0].id;
+        let mut queued = queue(&mut runtime, "queued", old);
+        let mut current = queue(&mut runtime, "current", new);
+
+        let (tx, _rx) = oneshot::channel();
+        runtime.release_generation("main".to_string(), old, tx);
+        assert!(matches!(queued.try_recv(), Err(TryRecvError::Closed)));
+        let tasks = runtime.poll_tasks();
+        assert_eq!(targets(&tasks), vec!["current", RELEASE_GENERATION_TARGET]);
+
+        // the result of a task the host already ran is ignored
+        runtime.complete_task(ok(running_id));
+        assert!(running.try_recv().is_err());
+        runtime.complete_task(ok(tasks[0].id));
+        assert!(current.try_recv().is_ok());
+
+        assert_eq!(runtime.load_report().discarded_tasks, 2);
+        // nothing of the old generation is left to wait for
+        assert!(runtime.retired_generations.is_empty());
+    }
+
+    #[test]
+    fn held_generations_reach_the_backend_on_commit() {
+        let mut runtime = runtime();
+        let generation = runtime.next_generation();
+        runtime.hold_generation(generation);
+        let _rx = queue(&mut runtime, "held", generation);
+        assert!(runtime.poll_tasks().is_empty());
+
+        runtime.commit_generation(generation);
+        assert_eq!(targets(&runtime.poll_tasks()), vec!["held"]);
+    }
+
+    #[test]
+    fn discarded_held_generations_never_reach_the_backend() {
+        let mut runtime = runtime();
+        let generation = runtime.next_generation();
+        runtime.hold_generation(generation);
+        runtime
+            .stash
+            .put("main", "state".to_string(), json!(1))
+            .unwrap();
+        assert_eq!(
+            runtime.take_stash("main", generation, "state"),
+            Some(json!(1))
+        );
+        let mut held = queue(&mut runtime, "held", generation);
+
+        let (tx, _rx) = oneshot::channel();
+        runtime.release_generation("main".to_string(), generation, tx);
+        assert!(matches!(held.try_recv(), Err(TryRecvError::Closed)));
+        assert_eq!(
+            targets(&runtime.poll_tasks()),
+            vec![RELEASE_GENERATION_TARGET]
+        );
+        // the stash is left as the discarded generation found it
+        assert_eq!(runtime.take_stash("main", 0, "state"), Some(json!(1)));
+        assert_eq!(runtime.load_report().discarded_tasks, 1);
+    }
+
+    #[test]
+    fn stopping_fails_waiting_tasks() {
+        let mut runtime = runtime();
+        let generation = runtime.next_generation();
+        let mut waiting = queue(&mut runtime, "waiting", generation);
+
+        runtime.shutdown();
+        match waiting.try_recv() {
+            Ok(TaskResultValue::Error(e)) => assert_eq!(e, RUNTIME_STOPPED),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+}
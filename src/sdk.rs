@@ -0,0 +1,91 @@
+use deno_core::{ModuleSpecifier, Url};
+
+/// The SDK modules compiled into the binary, keyed by their path within `sdk/`.
+/// Shipping them together with the ops they call keeps the two from drifting
+/// apart. They are transpiled to Javascript by `build.rs`, so loading them never
+/// needs the TypeScript compiler.
+static MODULES: &[(&str, &str)] = include!(concat!(env!("OUT_DIR"), "/sdk_modules.rs"));
+
+pub const SCHEME: &str = "dcs";
+const IMPORT_PREFIX: &str = "@dcs/";
+
+fn module_url(path: &str) -> ModuleSpecifier {
+    Url::parse(&format!("{}:///{}", SCHEME, path)).unwrap()
+}
+
+/// The module executed as the main module of every worker when no `sdk_path`
+/// override is configured. It exposes the whole SDK on `window` for the inspector.
+pub fn main_module() -> ModuleSpecifier {
+    module_url("index.ts")
+}
+
+/// Resolves `dcs:unit`, `dcs:unit.ts`, `@dcs/unit` and `@dcs/unit.ts` style
+/// specifiers to the embedded module they refer to.
+pub fn resolve(specifier: &str) -> Option<ModuleSpecifier> {
+    let name = specifier
+        .strip_prefix(IMPORT_PREFIX)
+        .or_else(|| specifier.strip_prefix("dcs:"))?;
+    let name = name.trim_start_matches('/');
+    let path = if name.ends_with(".ts") {
+        name.to_string()
+    } else {
+        format!("{}.ts", name)
+    };
+
+    if MODULES.iter().any(|(module_path, _)| *module_path == path) {
+        Some(module_url(&path))
+    } else {
+        None
+    }
+}
+
+/// Returns the (already transpiled) source of an embedded module.
+pub fn source(specifier: &ModuleSpecifier) -> Option<&'static str> {
+    if specifier.scheme() != SCHEME {
+        return None;
+    }
+    let path = specifier.path().trim_start_matches('/');
+    MODULES
+        .iter()
+        .find(|(module_path, _)| *module_path == path)
+        .map(|(_, source)| *source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_accepts_every_specifier_style() {
+        let expected = Url::parse("dcs:///unit.ts").unwrap();
+        for specifier in [
+            "@dcs/unit",
+            "@dcs/unit.ts",
+            "dcs:unit",
+            "dcs:unit.ts",
+            "dcs:///unit.ts",
+        ] {
+            assert_eq!(resolve(specifier), Some(expected.clone()), "{}", specifier);
+        }
+        assert_eq!(
+            resolve("@dcs/util/geo.ts"),
+            Some(Url::parse("dcs:///util/geo.ts").unwrap())
+        );
+    }
+
+    #[test]
+    fn resolve_rejects_unknown_modules() {
+        assert_eq!(resolve("@dcs/nope"), None);
+        assert_eq!(resolve("dcs:nope.ts"), None);
+        assert_eq!(resolve("./unit.ts"), None);
+        assert_eq!(resolve("https://example.com/@dcs/unit.ts"), None);
+    }
+
+    #[test]
+    fn resolved_modules_have_a_source() {
+        let specifier = resolve("@dcs/index.ts").unwrap();
+        assert_eq!(specifier, main_module());
+        assert!(source(&specifier).is_some());
+        assert!(source(&Url::parse("file:///index.ts").unwrap()).is_none());
+    }
+}
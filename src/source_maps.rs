@@ -0,0 +1,95 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use deno_core::error::{AnyError, JsError};
+use sourcemap::SourceMap;
+
+const SOURCE_MAP_PREFIX: &str = "//# sourceMappingURL=data:application/json;base64,";
+
+/// Source maps of the modules loaded into a worker, keyed by module url.
+#[derive(Clone, Default)]
+pub struct SourceMapStore {
+    maps: Rc<RefCell<HashMap<String, Rc<SourceMap>>>>,
+}
+
+impl SourceMapStore {
+    /// Records the inline source map of a compiled module, if it has one.
+    pub fn insert(&self, specifier: &str, code: &str) {
+        let encoded = match code
+            .lines()
+            .rev()
+            .find_map(|line| line.strip_prefix(SOURCE_MAP_PREFIX))
+        {
+            Some(encoded) => encoded,
+            None => return,
+        };
+
+        let map = base64::decode(encoded)
+            .ok()
+            .and_then(|json| SourceMap::from_slice(&json).ok());
+        if let Some(map) = map {
+            self.maps
+                .borrow_mut()
+                .insert(specifier.to_string(), Rc::new(map));
+        }
+    }
+
+    fn get(&self, specifier: &str) -> Option<Rc<SourceMap>> {
+        self.maps.borrow().get(specifier).cloned()
+    }
+
+    /// Maps the frames of `error` back to the original TypeScript sources and
+    /// rebuilds its stack from them.
+    pub fn apply(&self, mut error: JsError) -> JsError {
+        for frame in error.frames.iter_mut() {
+            let (file_name, line, column) =
+                match (&frame.file_name, frame.line_number, frame.column_number) {
+                    (Some(file_name), Some(line), Some(column)) => {
+                        (file_name.clone(), line, column)
+                    }
+                    _ => continue,
+                };
+            // V8 positions are 1-based, 0 stands for an unknown position
+            let (line, column) = match (to_index(line), to_index(column)) {
+                (Some(line), Some(column)) => (line, column),
+                _ => continue,
+            };
+            let map = match self.get(&file_name) {
+                Some(map) => map,
+                None => continue,
+            };
+            if let Some(token) = map.lookup_token(line, column) {
+                if let Some(source) = token.get_source() {
+                    frame.file_name = Some(source.to_string());
+                }
+                frame.line_number = Some(token.get_src_line() as i64 + 1);
+                frame.column_number = Some(token.get_src_col() as i64 + 1);
+            }
+        }
+
+        let mut stack = error.message.clone();
+        for frame in &error.frames {
+            let location = format!(
+                "{}:{}:{}",
+                frame.file_name.as_deref().unwrap_or("<unknown>"),
+                frame.line_number.unwrap_or(0),
+                frame.column_number.unwrap_or(0)
+            );
+            match &frame.function_name {
+                Some(name) => stack.push_str(&format!("\n    at {} ({})", name, location)),
+                None => stack.push_str(&format!("\n    at {}", location)),
+            }
+        }
+        error.stack = Some(stack);
+        error
+    }
+
+    /// Builds the `js_error_create_fn` for a worker using this store.
+    pub fn error_create_fn(&self) -> Rc<dyn Fn(JsError) -> AnyError> {
+        let store = self.clone();
+        Rc::new(move |error| store.apply(error).into())
+    }
+}
+
+fn to_index(position: i64) -> Option<u32> {
+    u32::try_from(position).ok()?.checked_sub(1)
+}
@@ -0,0 +1,313 @@
+use std::{
+    borrow::Cow,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use deno_core::{anyhow::Error, error::generic_error, Resource};
+use rusqlite::{
+    types::{Value, ValueRef},
+    Connection, Statement,
+};
+use serde::{Deserialize, Serialize};
+
+/// Parameters of a statement, either positional (`?`) or named (`:name`).
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum SqlParams {
+    Positional(Vec<serde_json::Value>),
+    Named(serde_json::Map<String, serde_json::Value>),
+}
+
+impl Default for SqlParams {
+    fn default() -> Self {
+        SqlParams::Positional(vec![])
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SqlStatement {
+    pub sql: String,
+    #[serde(default)]
+    pub params: SqlParams,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecuteResult {
+    pub changes: usize,
+    pub last_insert_rowid: i64,
+}
+
+fn to_sql_value(value: &serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(value) => Value::Integer(*value as i64),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(value) => Value::Integer(value),
+            None => Value::Real(number.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(value) => Value::Text(value.clone()),
+        // arrays and objects are stored as JSON text, usable with sqlite's json
+        //  functions
+        value => Value::Text(value.to_string()),
+    }
+}
+
+fn to_json_value(value: ValueRef) -> serde_json::Value {
+    match value {
+        ValueRef::Null => serde_json::Value::Null,
+        ValueRef::Integer(value) => value.into(),
+        ValueRef::Real(value) => value.into(),
+        ValueRef::Text(value) => String::from_utf8_lossy(value).into(),
+        ValueRef::Blob(value) => value.to_vec().into(),
+    }
+}
+
+fn bind(statement: &mut Statement, params: &SqlParams) -> Result<(), Error> {
+    match params {
+        SqlParams::Positional(values) => {
+            for (index, value) in values.iter().enumerate() {
+                statement.raw_bind_parameter(index + 1, to_sql_value(value))?;
+            }
+        }
+        SqlParams::Named(values) => {
+            for (name, value) in values {
+                let name: Cow<str> = if name.starts_with(&[':', '@', '$'][..]) {
+                    name.into()
+                } else {
+                    format!(":{}", name).into()
+                };
+                let index = statement
+                    .parameter_index(&name)?
+                    .ok_or_else(|| generic_error(format!("unknown parameter {}", name)))?;
+                statement.raw_bind_parameter(index, to_sql_value(value))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs a statement not returning rows. Statements are prepared once per
+/// connection and reused for the same SQL.
+fn execute(connection: &Connection, statement: &SqlStatement) -> Result<ExecuteResult, Error> {
+    let mut prepared = connection.prepare_cached(&statement.sql)?;
+    bind(&mut prepared, &statement.params)?;
+    let changes = prepared.raw_execute()?;
+    Ok(ExecuteResult {
+        changes,
+        last_insert_rowid: connection.last_insert_rowid(),
+    })
+}
+
+/// Runs a statement returning its rows as objects keyed by column name.
+fn query(
+    connection: &Connection,
+    statement: &SqlStatement,
+) -> Result<Vec<serde_json::Map<String, serde_json::Value>>, Error> {
+    let mut prepared = connection.prepare_cached(&statement.sql)?;
+    let columns: Vec<String> = prepared
+        .column_names()
+        .into_iter()
+        .map(String::from)
+        .collect();
+    bind(&mut prepared, &statement.params)?;
+
+    let mut rows = prepared.raw_query();
+    let mut result = vec![];
+    while let Some(row) = rows.next()? {
+        let mut object = serde_json::Map::new();
+        for (index, column) in columns.iter().enumerate() {
+            object.insert(column.clone(), to_json_value(row.get_ref(index)?));
+        }
+        result.push(object);
+    }
+    Ok(result)
+}
+
+/// A SQLite database opened by a script. The connection is only used from the
+/// blocking thread pool, the mutex serializes statements of concurrent calls.
+pub struct Database {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl Resource for Database {
+    fn name(&self) -> Cow<str> {
+        "SqliteDatabase".into()
+    }
+}
+
+impl Database {
+    /// Opens (creating if needed) the database at `path` relative to `data_dir`.
+    /// Paths may not leave `data_dir`, `:memory:` opens an in-memory database.
+    pub fn open(data_dir: &Path, path: &str) -> Result<Self, Error> {
+        let connection = if path == ":memory:" {
+            Connection::open_in_memory()?
+        } else {
+            let relative = PathBuf::from(path);
+            if relative.components().next().is_none()
+                || relative
+                    .components()
+                    .any(|component| !matches!(component, Component::Normal(_)))
+            {
+                return Err(generic_error(format!(
+                    "database path {} must be relative to {} and stay within it",
+                    path,
+                    data_dir.display()
+                )));
+            }
+            let full_path = data_dir.join(relative);
+            if let Some(parent) = full_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            Connection::open(full_path)?
+        };
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    fn connection(&self) -> Arc<Mutex<Connection>> {
+        self.connection.clone()
+    }
+
+    pub async fn execute(&self, statement: SqlStatement) -> Result<ExecuteResult, Error> {
+        let connection = self.connection();
+        tokio::task::spawn_blocking(move || execute(&connection.lock().unwrap(), &statement))
+            .await?
+    }
+
+    pub async fn query(
+        &self,
+        statement: SqlStatement,
+    ) -> Result<Vec<serde_json::Map<String, serde_json::Value>>, Error> {
+        let connection = self.connection();
+        tokio::task::spawn_blocking(move || query(&connection.lock().unwrap(), &statement)).await?
+    }
+
+    /// Runs `statements` within a single transaction, rolling all of them back if
+    /// any fails.
+    pub async fn transaction(
+        &self,
+        statements: Vec<SqlStatement>,
+    ) -> Result<Vec<ExecuteResult>, Error> {
+        let connection = self.connection();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().unwrap();
+            let transaction = connection.transaction()?;
+            let results = statements
+                .iter()
+                .map(|statement| execute(&transaction, statement))
+                .collect::<Result<Vec<_>, Error>>()?;
+            transaction.commit()?;
+            Ok(results)
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn data_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("dcs-ts-sqlite-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn statement(sql: &str, params: serde_json::Value) -> SqlStatement {
+        serde_json::from_value(json!({ "sql": sql, "params": params })).unwrap()
+    }
+
+    #[test]
+    fn paths_must_stay_within_the_data_dir() {
+        let dir = data_dir("paths");
+        for path in [
+            "../escape.db",
+            "stats/../../escape.db",
+            "/tmp/escape.db",
+            "./stats.db",
+            "",
+        ] {
+            assert!(Database::open(&dir, path).is_err(), "{}", path);
+        }
+        assert!(!dir.parent().unwrap().join("escape.db").exists());
+
+        Database::open(&dir, "stats/kills.db").unwrap();
+        assert!(dir.join("stats/kills.db").exists());
+        Database::open(&dir, ":memory:").unwrap();
+    }
+
+    #[test]
+    fn params_are_bound_by_position_or_name() {
+        let database = Database::open(&data_dir("params"), ":memory:").unwrap();
+        let connection = database.connection.lock().unwrap();
+        execute(
+            &connection,
+            &statement("CREATE TABLE t (a, b, c)", json!([])),
+        )
+        .unwrap();
+        let result = execute(
+            &connection,
+            &statement(
+                "INSERT INTO t (a, b, c) VALUES (?, ?, ?)",
+                json!([true, 1.5, { "x": 1 }]),
+            ),
+        )
+        .unwrap();
+        assert_eq!(result.changes, 1);
+        assert_eq!(result.last_insert_rowid, 1);
+        execute(
+            &connection,
+            &statement(
+                "INSERT INTO t (a, b, c) VALUES (:a, @b, $c)",
+                json!({ "a": null, "@b": 2, "$c": "text" }),
+            ),
+        )
+        .unwrap();
+
+        let rows = query(&connection, &statement("SELECT * FROM t", json!([]))).unwrap();
+        assert_eq!(
+            serde_json::to_value(&rows).unwrap(),
+            json!([
+                { "a": 1, "b": 1.5, "c": "{\"x\":1}" },
+                { "a": null, "b": 2, "c": "text" },
+            ])
+        );
+
+        let unknown = statement("SELECT * FROM t WHERE a = :a", json!({ "b": 1 }));
+        assert!(query(&connection, &unknown).is_err());
+    }
+
+    #[test]
+    fn transactions_roll_back_as_a_whole() {
+        let database = Database::open(&data_dir("transaction"), ":memory:").unwrap();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            database
+                .execute(statement("CREATE TABLE t (a UNIQUE)", json!([])))
+                .await
+                .unwrap();
+            let insert = |value: i64| statement("INSERT INTO t (a) VALUES (?)", json!([value]));
+            assert!(database
+                .transaction(vec![insert(1), insert(2), insert(1)])
+                .await
+                .is_err());
+            let results = database
+                .transaction(vec![insert(1), insert(2)])
+                .await
+                .unwrap();
+            assert_eq!(results.len(), 2);
+            let rows = database
+                .query(statement("SELECT a FROM t ORDER BY a", json!([])))
+                .await
+                .unwrap();
+            assert_eq!(rows.len(), 2);
+        });
+    }
+}
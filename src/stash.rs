@@ -0,0 +1,285 @@
+use std::{collections::BTreeMap, fs, path::PathBuf, sync::mpsc, thread};
+
+use deno_core::{anyhow::Error, error::generic_error};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::MissionInfo, loader::write_atomic};
+
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct StashConfig {
+    /// Largest single entry in bytes of serialized JSON.
+    pub max_entry_bytes: usize,
+    /// Largest size of all entries together in bytes of serialized JSON.
+    pub max_total_bytes: usize,
+    /// Keep the stash in `Data/ts-stash.json` so it survives restarting DCS. It
+    /// is only handed to a later run of the same mission.
+    pub persist: bool,
+}
+
+impl Default for StashConfig {
+    fn default() -> Self {
+        Self {
+            max_entry_bytes: 1024 * 1024,
+            max_total_bytes: 16 * 1024 * 1024,
+            persist: false,
+        }
+    }
+}
+
+// worker name -> entry name -> serialized JSON
+type Entries = BTreeMap<String, BTreeMap<String, String>>;
+
+#[derive(Default, Deserialize, Serialize)]
+struct PersistedStash {
+    /// The mission the entries were stashed in, `None` outside of a mission.
+    mission: Option<String>,
+    entries: Entries,
+}
+
+/// State handed from a worker to its next generation across a reload. Entries are
+/// keyed by worker and name, and are removed once taken.
+pub struct Stash {
+    config: StashConfig,
+    mission: Option<String>,
+    // snapshots to persist, written by a background thread so changes never wait
+    //  for the disk while holding the runtime
+    persist_tx: Option<mpsc::Sender<Vec<u8>>>,
+    entries: Entries,
+}
+
+impl Stash {
+    pub fn new(
+        write_dir: Option<&str>,
+        config: &StashConfig,
+        mission: Option<&MissionInfo>,
+    ) -> Self {
+        let path = write_dir.filter(|_| config.persist).map(|write_dir| {
+            let mut path = PathBuf::from(write_dir);
+            path.push("Data/ts-stash.json");
+            path
+        });
+        let mission =
+            mission.and_then(|mission| mission.file.clone().or_else(|| mission.name.clone()));
+
+        let persisted: PersistedStash = path
+            .as_ref()
+            .and_then(|path| match fs::read(path) {
+                Ok(contents) => match serde_json::from_slice(&contents) {
+                    Ok(persisted) => Some(persisted),
+                    Err(e) => {
+                        log::warn!("ignoring invalid stash {}: {}", path.display(), e);
+                        None
+                    }
+                },
+                Err(_) => None,
+            })
+            .unwrap_or_default();
+        // entries of another mission are meaningless to the scripts of this one
+        let stale = persisted.mission != mission && !persisted.entries.is_empty();
+        let entries = if stale {
+            log::info!(
+                "dropping stash of mission {}",
+                persisted.mission.as_deref().unwrap_or("<none>")
+            );
+            Entries::new()
+        } else {
+            persisted.entries
+        };
+
+        let persist_tx = path.and_then(|path| match spawn_writer(path) {
+            Ok(tx) => Some(tx),
+            Err(e) => {
+                log::error!("failed to start stash writer: {}", e);
+                None
+            }
+        });
+        let stash = Self {
+            config: config.clone(),
+            mission,
+            persist_tx,
+            entries,
+        };
+        if stale {
+            stash.persist();
+        }
+        stash
+    }
+
+    fn total_bytes(&self) -> usize {
+        self.entries
+            .values()
+            .flat_map(|entries| entries.values())
+            .map(|value| value.len())
+            .sum()
+    }
+
+    /// Stores `value` under `key` for `worker`, replacing any previous entry.
+    pub fn put(
+        &mut self,
+        worker: &str,
+        key: String,
+        value: serde_json::Value,
+    ) -> Result<(), Error> {
+        let value = serde_json::to_string(&value)?;
+        if value.len() > self.config.max_entry_bytes {
+            return Err(generic_error(format!(
+                "stash entry {} is {} bytes, the limit is {}",
+                key,
+                value.len(),
+                self.config.max_entry_bytes
+            )));
+        }
+
+        let replaced = self
+            .entries
+            .get(worker)
+            .and_then(|entries| entries.get(&key))
+            .map(|value| value.len())
+            .unwrap_or(0);
+        let total = self.total_bytes() - replaced + value.len();
+        if total > self.config.max_total_bytes {
+            return Err(generic_error(format!(
+                "stash entry {} would grow the stash to {} bytes, the limit is {}",
+                key, total, self.config.max_total_bytes
+            )));
+        }
+
+        self.entries
+            .entry(worker.to_string())
+            .or_default()
+            .insert(key, value);
+        self.persist();
+        Ok(())
+    }
+
+    /// Removes and returns the entry stored under `key` for `worker`.
+    pub fn take(&mut self, worker: &str, key: &str) -> Option<serde_json::Value> {
+        let entries = self.entries.get_mut(worker)?;
+        let value = entries.remove(key)?;
+        if entries.is_empty() {
+            self.entries.remove(worker);
+        }
+        self.persist();
+        serde_json::from_str(&value).ok()
+    }
+
+    /// Puts back an entry taken by a worker generation that was discarded, unless
+    /// the worker stored a new one under the same key meanwhile.
+    pub fn restore(&mut self, worker: &str, key: String, value: serde_json::Value) {
+        let value = match serde_json::to_string(&value) {
+            Ok(value) => value,
+            Err(e) => {
+                log::error!("failed to restore stash entry {}: {}", key, e);
+                return;
+            }
+        };
+        self.entries
+            .entry(worker.to_string())
+            .or_default()
+            .entry(key)
+            .or_insert(value);
+        self.persist();
+    }
+
+    fn persist(&self) {
+        let tx = match &self.persist_tx {
+            Some(tx) => tx,
+            None => return,
+        };
+        let persisted = PersistedStash {
+            mission: self.mission.clone(),
+            entries: self.entries.clone(),
+        };
+        match serde_json::to_vec(&persisted) {
+            Ok(contents) => {
+                let _ = tx.send(contents);
+            }
+            Err(e) => log::error!("failed to serialize stash: {}", e),
+        }
+    }
+}
+
+// writes the latest snapshot once the previous write finished, skipping the ones
+//  superseded meanwhile. Snapshots still queued when the stash is dropped are
+//  written before the thread ends.
+fn spawn_writer(path: PathBuf) -> Result<mpsc::Sender<Vec<u8>>, Error> {
+    let (tx, rx) = mpsc::channel::<Vec<u8>>();
+    thread::Builder::new()
+        .name("dcs-ts-stash".to_string())
+        .spawn(move || {
+            while let Ok(mut contents) = rx.recv() {
+                while let Ok(newer) = rx.try_recv() {
+                    contents = newer;
+                }
+                if let Err(e) = write_atomic(&path, &contents) {
+                    log::error!("failed to persist stash to {}: {}", path.display(), e);
+                }
+            }
+        })?;
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn stash(max_entry_bytes: usize, max_total_bytes: usize) -> Stash {
+        let config = StashConfig {
+            max_entry_bytes,
+            max_total_bytes,
+            persist: false,
+        };
+        Stash::new(None, &config, None)
+    }
+
+    #[test]
+    fn entries_are_taken_once_per_worker() {
+        let mut stash = stash(1024, 1024);
+        stash
+            .put("main", "state".to_string(), json!({ "a": 1 }))
+            .unwrap();
+        assert_eq!(stash.take("other", "state"), None);
+        assert_eq!(stash.take("main", "state"), Some(json!({ "a": 1 })));
+        assert_eq!(stash.take("main", "state"), None);
+    }
+
+    #[test]
+    fn entries_over_the_entry_limit_are_rejected() {
+        let mut stash = stash(8, 1024);
+        // `"123456"` is 8 bytes of JSON
+        stash.put("main", "a".to_string(), json!("123456")).unwrap();
+        assert!(stash
+            .put("main", "b".to_string(), json!("1234567"))
+            .is_err());
+        assert_eq!(stash.take("main", "b"), None);
+    }
+
+    #[test]
+    fn entries_over_the_total_limit_are_rejected() {
+        let mut stash = stash(8, 16);
+        stash.put("main", "a".to_string(), json!("123456")).unwrap();
+        stash
+            .put("other", "a".to_string(), json!("123456"))
+            .unwrap();
+        assert!(stash.put("main", "b".to_string(), json!(1)).is_err());
+
+        // replacing an entry only counts the difference
+        stash.put("main", "a".to_string(), json!("654321")).unwrap();
+        stash.take("other", "a").unwrap();
+        stash.put("main", "b".to_string(), json!(1)).unwrap();
+    }
+
+    #[test]
+    fn restored_entries_do_not_replace_newer_ones() {
+        let mut stash = stash(1024, 1024);
+        stash.restore("main", "a".to_string(), json!(1));
+        stash.put("main", "b".to_string(), json!(2)).unwrap();
+        stash.restore("main", "b".to_string(), json!(1));
+        assert_eq!(stash.take("main", "a"), Some(json!(1)));
+        assert_eq!(stash.take("main", "b"), Some(json!(2)));
+    }
+}